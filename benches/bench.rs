@@ -1,4 +1,3 @@
-#[macro_use]
 extern crate criterion;
 
 use criterion::*;
@@ -13,6 +12,7 @@ extern crate tempfile;
 
 use flumedb::flume_log::FlumeLog;
 use flumedb::mem_log::MemLog;
+use flumedb::mmap_offset_log::MmapOffsetLog;
 use flumedb::offset_log::*;
 use serde_json::{from_slice, Value};
 use tempfile::tempfile;
@@ -29,6 +29,10 @@ fn temp_offset_log() -> OffsetLog<u32> {
     OffsetLog::<u32>::from_file(tempfile().unwrap()).unwrap()
 }
 
+fn temp_mmap_offset_log() -> MmapOffsetLog<u32> {
+    MmapOffsetLog::<u32>::from_file(tempfile().unwrap()).unwrap()
+}
+
 fn offset_log_decode(c: &mut Criterion) {
     c.bench_function("offset_log_decode", |b| {
         b.iter(|| {
@@ -56,6 +60,31 @@ fn offset_log_append(c: &mut Criterion) {
     });
 }
 
+fn offset_log_append_buffered(c: &mut Criterion) {
+    let mut group = c.benchmark_group("offset log 100k small appends");
+    group.sample_size(10);
+    for &capacity in &[0, 8 * 1024, 64 * 1024] {
+        let name = format!("{}KiB write buffer", capacity / 1024);
+        group.bench_function(&name, move |b| {
+            b.iter_batched(
+                || {
+                    let mut log = temp_offset_log();
+                    log.set_write_buffer_capacity(capacity).unwrap();
+                    log
+                },
+                |mut log| {
+                    for i in 0..100_000u32 {
+                        log.append(&i.to_be_bytes()).unwrap();
+                    }
+                    log.flush().unwrap();
+                },
+                BatchSize::PerIteration,
+            )
+        });
+    }
+    group.finish();
+}
+
 fn offset_log_append_batch(c: &mut Criterion) {
     let test_bufs = default_test_bufs();
     c.bench_function("offset log append batch - all", move |b| {
@@ -76,7 +105,7 @@ fn offset_log_append_batch(c: &mut Criterion) {
             temp_offset_log,
             |mut log| {
                 for chunk in test_bufs.chunks(100) {
-                    let offsets = log.append_batch(&chunk).unwrap();
+                    let offsets = log.append_batch(chunk).unwrap();
                     assert_eq!(offsets.len(), chunk.len());
                 }
             },
@@ -106,7 +135,7 @@ fn offset_log_iter(c: &mut Criterion) {
 
     c.bench_function("offset log iter forward", move |b| {
         b.iter_batched(
-            || log.iter(),
+            || log.bidir_iter(),
             |mut iter| {
                 let count = iter.forward().count();
                 assert_eq!(count, offsets.len());
@@ -121,7 +150,7 @@ fn offset_log_iter(c: &mut Criterion) {
 
     c.bench_function("offset log iter backward", move |b| {
         b.iter_batched(
-            || log.iter_at_offset(log.end()),
+            || log.bidir_iter_at_offset(log.end()),
             |mut iter| {
                 let count = iter.backward().count();
                 assert_eq!(count, offsets.len());
@@ -136,7 +165,7 @@ fn offset_log_iter(c: &mut Criterion) {
 
     c.bench_function("offset log iter forward and json decode", move |b| {
         b.iter_batched(
-            || log.iter(),
+            || log.bidir_iter(),
             |mut iter| {
                 let sum: u64 = iter
                     .forward()
@@ -156,6 +185,111 @@ fn offset_log_iter(c: &mut Criterion) {
     });
 }
 
+// Entry sizes used by the scan/get/append benchmarks below.
+const ENTRY_SIZES: &[usize] = &[16, 1024, 64 * 1024];
+const HARNESS_BYTES: usize = 16 * 1024 * 1024;
+
+fn entry_of_size(size: usize) -> Vec<u8> {
+    (0..size).map(|i| (i % 251) as u8).collect()
+}
+
+// Append enough entries of `size` bytes to fill roughly HARNESS_BYTES,
+// returning their sequence numbers.
+fn populate<L: FlumeLog>(log: &mut L, size: usize) -> Vec<u64> {
+    let entry = entry_of_size(size);
+    (0..(HARNESS_BYTES / size).max(1))
+        .map(|_| log.append(&entry).unwrap())
+        .collect()
+}
+
+// A deterministic shuffle (xorshift), so runs are comparable.
+fn shuffled(mut seqs: Vec<u64>) -> Vec<u64> {
+    let mut state: u64 = 0x2545_f491_4f6c_dd1d;
+    for i in (1..seqs.len()).rev() {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        seqs.swap(i, (state % (i as u64 + 1)) as usize);
+    }
+    seqs
+}
+
+fn bench_append<L: FlumeLog, F: Fn() -> L>(c: &mut Criterion, name: &str, new_log: F) {
+    let mut group = c.benchmark_group(format!("{} append", name));
+    for size in ENTRY_SIZES {
+        let entry = entry_of_size(*size);
+        let count = (HARNESS_BYTES / size).max(1);
+        group.throughput(Throughput::Bytes((count * size) as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, _| {
+            b.iter_batched(
+                &new_log,
+                |mut log| {
+                    for _ in 0..count {
+                        log.append(&entry).unwrap();
+                    }
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+fn bench_random_get<L: FlumeLog, F: Fn() -> L>(c: &mut Criterion, name: &str, new_log: F) {
+    let mut group = c.benchmark_group(format!("{} random get", name));
+    for size in ENTRY_SIZES {
+        let mut log = new_log();
+        let seqs = shuffled(populate(&mut log, *size));
+        group.throughput(Throughput::Elements(seqs.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, size| {
+            b.iter(|| {
+                for seq in seqs.iter() {
+                    assert_eq!(log.get(*seq).unwrap().len(), *size);
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn offset_log_iter_large_entries(c: &mut Criterion) {
+    let mut log = temp_offset_log();
+    let offsets = populate(&mut log, 256 * 1024);
+
+    for &capacity in &[8 * 1024, 1024 * 1024] {
+        let file = log.file.try_clone().unwrap();
+        let count = offsets.len();
+        let name = format!(
+            "offset log iter 256KiB entries - {}KiB buffer",
+            capacity / 1024
+        );
+        c.bench_function(&name, move |b| {
+            b.iter_batched(
+                || OffsetLogIter::<u32>::with_capacity(file.try_clone().unwrap(), capacity),
+                |mut iter| assert_eq!(iter.forward().count(), count),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+}
+
+fn offset_log_harness(c: &mut Criterion) {
+    bench_append(c, "offset log", temp_offset_log);
+    bench_random_get(c, "offset log", temp_offset_log);
+    bench_random_get(c, "mmap offset log", temp_mmap_offset_log);
+
+    let mut group = c.benchmark_group("offset log scan");
+    for size in ENTRY_SIZES {
+        let mut log = temp_offset_log();
+        let count = populate(&mut log, *size).len();
+        group.throughput(Throughput::Bytes(log.end()));
+        group.bench_with_input(BenchmarkId::from_parameter(size), size, |b, _| {
+            b.iter(|| assert_eq!(log.iter().count(), count))
+        });
+    }
+    group.finish();
+}
+
 fn mem_log_get(c: &mut Criterion) {
     let mut log = MemLog::new();
     let test_buf = DEFAULT_TEST_BUF;
@@ -182,7 +316,7 @@ fn mem_log_append(c: &mut Criterion) {
                 .map(|_| log.append(DEFAULT_TEST_BUF).unwrap())
                 .collect();
 
-            assert_eq!(offsets.len(), NUM_ENTRIES as usize);
+            assert_eq!(offsets.len(), NUM_ENTRIES);
         })
     });
 }
@@ -198,12 +332,9 @@ fn mem_log_iter(c: &mut Criterion) {
         b.iter(|| {
             let sum: u64 = log
                 .into_iter()
-                .map(|val| from_slice(&val).unwrap())
+                .map(|val| from_slice(val).unwrap())
                 .map(|val: Value| match val["value"] {
-                    Value::Number(ref num) => {
-                        let result = num.as_u64().unwrap();
-                        result
-                    }
+                    Value::Number(ref num) => num.as_u64().unwrap(),
                     _ => panic!(),
                 })
                 .sum();
@@ -216,7 +347,13 @@ fn mem_log_iter(c: &mut Criterion) {
 criterion_group! {
 name = offset_log;
 config = Criterion::default().sample_size(10);
-targets = offset_log_get, offset_log_append, offset_log_append_batch, offset_log_iter, offset_log_decode
+targets = offset_log_get, offset_log_append, offset_log_append_buffered, offset_log_append_batch, offset_log_iter, offset_log_iter_large_entries, offset_log_decode
+}
+
+criterion_group! {
+name = harness;
+config = Criterion::default().sample_size(10);
+targets = offset_log_harness
 }
 
 criterion_group! {
@@ -225,4 +362,4 @@ config = Criterion::default().sample_size(10);
 targets = mem_log_get, mem_log_append, mem_log_iter
 }
 
-criterion_main!(offset_log, mem_log);
+criterion_main!(offset_log, mem_log, harness);
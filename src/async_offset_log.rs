@@ -0,0 +1,199 @@
+use crate::flume_log::*;
+use crate::offset_log::{
+    encode, size_of_frame_tail, size_of_framing_bytes, validate_entry, FlumeOffsetLogError,
+    OffsetType,
+};
+use bytes::BytesMut;
+use std::future::Future;
+use std::io::SeekFrom;
+use std::marker::PhantomData;
+use std::path::Path;
+use tokio::fs::{File, OpenOptions};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt};
+use tokio::sync::Mutex;
+
+/// The async counterpart of `FlumeLog`, for use from tokio runtimes.
+pub trait AsyncFlumeLog {
+    fn get(&self, seq: Sequence) -> impl Future<Output = Result<Vec<u8>, Error>> + Send;
+    fn latest(&self) -> impl Future<Output = Option<Sequence>> + Send;
+    fn append(&mut self, buff: &[u8]) -> impl Future<Output = Result<Sequence, Error>> + Send;
+}
+
+/// An `OffsetLog` that does its IO through `tokio::fs::File`.
+///
+/// The on-disk format is the same as `OffsetLog<ByteType>`, so a log
+/// written by one can be opened by the other.
+pub struct AsyncOffsetLog<ByteType> {
+    // Reads have to seek, so even `get` needs exclusive use of the file.
+    file: Mutex<File>,
+    end_of_file: u64,
+    last_offset: Option<u64>,
+    tmp_buffer: BytesMut,
+    byte_type: PhantomData<ByteType>,
+}
+
+impl<ByteType> AsyncOffsetLog<ByteType> {
+    pub async fn new<P: AsRef<Path>>(path: P) -> Result<AsyncOffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .await
+            .map_err(|error| {
+                let path = path.as_ref().display().to_string();
+                FlumeOffsetLogError::OpenFailed { path, error }
+            })?;
+
+        AsyncOffsetLog::from_file(file).await
+    }
+
+    pub async fn from_file(mut file: File) -> Result<AsyncOffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        let file_length = file
+            .seek(SeekFrom::End(0))
+            .await
+            .map_err(|error| FlumeOffsetLogError::StatFailed { error })?;
+
+        let last_offset = if file_length > 0 {
+            Some(last_frame_offset::<ByteType>(&mut file, file_length).await?)
+        } else {
+            None
+        };
+
+        Ok(AsyncOffsetLog {
+            file: Mutex::new(file),
+            end_of_file: file_length,
+            last_offset,
+            tmp_buffer: BytesMut::new(),
+            byte_type: PhantomData,
+        })
+    }
+
+    /// The offset just past the last entry. See `OffsetLog::end`.
+    pub fn end(&self) -> u64 {
+        self.end_of_file
+    }
+
+    async fn read(&self, offset: u64) -> Result<Vec<u8>, Error> {
+        let invalid = || -> Error { FlumeOffsetLogError::InvalidOffset { offset }.into() };
+        if offset >= self.end_of_file {
+            return Err(invalid());
+        }
+
+        let mut file = self.file.lock().await;
+        file.seek(SeekFrom::Start(offset)).await?;
+        let data_size = file.read_u32().await? as usize;
+        let frame_size = (size_of_framing_bytes::<ByteType>() + data_size) as u64;
+        if frame_size > self.end_of_file - offset {
+            return Err(invalid());
+        }
+
+        let mut buf = vec![0; data_size + size_of_frame_tail::<ByteType>()];
+        file.read_exact(&mut buf).await?;
+        validate_entry::<ByteType>(offset, data_size, &buf).map_err(|_| invalid())?;
+
+        buf.truncate(data_size);
+        Ok(buf)
+    }
+}
+
+impl<ByteType: Send + Sync> AsyncFlumeLog for AsyncOffsetLog<ByteType> {
+    async fn get(&self, seq_num: Sequence) -> Result<Vec<u8>, Error> {
+        self.read(seq_num).await
+    }
+
+    async fn latest(&self) -> Option<Sequence> {
+        self.last_offset
+    }
+
+    async fn append(&mut self, buff: &[u8]) -> Result<Sequence, Error> {
+        let offset = self.end_of_file;
+        self.tmp_buffer.clear();
+        let next = encode::<ByteType>(offset, buff, &mut self.tmp_buffer)?;
+
+        let file = self.file.get_mut();
+        file.seek(SeekFrom::Start(offset)).await?;
+        if let Err(e) = file.write_all(&self.tmp_buffer).await {
+            // As with `OffsetLog`, don't leave part of a frame behind.
+            let _ = file.set_len(offset).await;
+            return Err(e.into());
+        }
+        file.flush().await?;
+
+        self.end_of_file = next;
+        self.last_offset = Some(offset);
+        Ok(offset)
+    }
+}
+
+// Find the offset of the last frame from the tail at the end of the file.
+async fn last_frame_offset<ByteType>(file: &mut File, file_length: u64) -> Result<u64, Error> {
+    let tail_size = size_of_frame_tail::<ByteType>() as u64;
+    if tail_size > file_length {
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    }
+
+    file.seek(SeekFrom::Start(file_length - tail_size)).await?;
+    let data_size = file.read_u32().await? as u64;
+    let frame_size = size_of_framing_bytes::<ByteType>() as u64 + data_size;
+    if frame_size > file_length {
+        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+    }
+    Ok(file_length - frame_size)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::async_offset_log::*;
+    use crate::offset_log::OffsetLog;
+
+    extern crate tempfile;
+    use self::tempfile::tempdir;
+
+    #[tokio::test]
+    async fn append_and_get() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let mut log = AsyncOffsetLog::<u32>::new(dir.path().join("log.offset")).await?;
+        assert_eq!(log.latest().await, None);
+        assert!(log.get(0).await.is_err());
+
+        let a = log.append(b"abc").await?;
+        let b = log.append(b"").await?;
+        let c = log.append(b"defgh").await?;
+        assert_eq!(log.get(a).await?, b"abc");
+        assert_eq!(log.get(b).await?, b"");
+        assert_eq!(log.get(c).await?, b"defgh");
+        assert_eq!(log.latest().await, Some(c));
+        assert!(log.get(c + 1).await.is_err());
+        assert!(log.get(log.end()).await.is_err());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn same_format_as_offset_log() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log.offset");
+
+        let mut log = AsyncOffsetLog::<u64>::new(&path).await?;
+        let a = log.append(b"written async").await?;
+        drop(log);
+
+        let mut sync_log = OffsetLog::<u64>::new(&path)?;
+        assert_eq!(sync_log.get(a)?, b"written async");
+        let b = sync_log.append(b"written sync")?;
+        drop(sync_log);
+
+        let log = AsyncOffsetLog::<u64>::new(&path).await?;
+        assert_eq!(log.latest().await, Some(b));
+        assert_eq!(log.get(a).await?, b"written async");
+        assert_eq!(log.get(b).await?, b"written sync");
+        Ok(())
+    }
+}
@@ -0,0 +1,152 @@
+use crate::crc32::crc32;
+use crate::flume_log::*;
+use crate::log_entry::LogEntry;
+use crate::offset_log::{write_all_at, FlumeOffsetLogError, OffsetLog, OffsetType};
+use byteorder::{BigEndian, ReadBytesExt};
+use std::fs::File;
+use std::mem::size_of;
+use std::path::Path;
+
+const CHECKSUM_SIZE: usize = size_of::<u32>();
+
+/// An `OffsetLog` whose entries carry a CRC32 of their data, so corruption
+/// inside the data is caught on read rather than silently returned.
+///
+/// The checksum is stored after the data, inside the frame, so the framing
+/// is the same as any other `OffsetLog<ByteType>`. Only logs written
+/// through a `ChecksummedOffsetLog` can be read by one.
+pub struct ChecksummedOffsetLog<ByteType> {
+    log: OffsetLog<ByteType>,
+}
+
+impl<ByteType> ChecksummedOffsetLog<ByteType> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<ChecksummedOffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        Ok(ChecksummedOffsetLog {
+            log: OffsetLog::new(path)?,
+        })
+    }
+
+    pub fn from_file(file: File) -> Result<ChecksummedOffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        Ok(ChecksummedOffsetLog {
+            log: OffsetLog::from_file(file)?,
+        })
+    }
+
+    pub fn end(&self) -> u64 {
+        self.log.end()
+    }
+
+    /// Iterate over the entries of the log, checking each one. Iteration
+    /// stops at the first entry that fails its check.
+    pub fn iter(&self) -> impl Iterator<Item = LogEntry> {
+        self.log
+            .iter()
+            .map(|e| {
+                let offset = e.offset;
+                verify_checksum(offset, e.data).map(|data| LogEntry { offset, data })
+            })
+            .take_while(Result::is_ok)
+            .filter_map(Result::ok)
+    }
+}
+
+impl<ByteType> FlumeLog for ChecksummedOffsetLog<ByteType> {
+    fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
+        verify_checksum(seq_num, self.log.read(seq_num)?.entry.data)
+    }
+
+    fn latest(&self) -> Option<u64> {
+        self.log.latest()
+    }
+
+    fn append(&mut self, buff: &[u8]) -> Result<u64, Error> {
+        let mut data = Vec::with_capacity(buff.len() + CHECKSUM_SIZE);
+        data.extend_from_slice(buff);
+        data.extend_from_slice(&crc32(buff).to_be_bytes());
+        self.log.append(&data)
+    }
+
+    /// Zero the data of an entry. The checksum is rewritten to match, so a
+    /// cleared entry still reads back without error.
+    fn clear(&mut self, seq_num: u64) -> Result<(), Error> {
+        let data_size = self.get(seq_num)?.len();
+        self.log.clear(seq_num)?;
+
+        let checksum_start = seq_num + (size_of::<u32>() + data_size) as u64;
+        let checksum = crc32(&vec![0; data_size]).to_be_bytes();
+        write_all_at(&self.log.file, &checksum, checksum_start)?;
+        Ok(())
+    }
+}
+
+// Check and strip the checksum from the end of an entry's data.
+fn verify_checksum(offset: u64, mut data: Vec<u8>) -> Result<Vec<u8>, Error> {
+    if data.len() < CHECKSUM_SIZE {
+        return Err(FlumeOffsetLogError::ChecksumMismatch { offset }.into());
+    }
+    let data_size = data.len() - CHECKSUM_SIZE;
+    let stored = (&data[data_size..]).read_u32::<BigEndian>()?;
+    if stored != crc32(&data[..data_size]) {
+        return Err(FlumeOffsetLogError::ChecksumMismatch { offset }.into());
+    }
+    data.truncate(data_size);
+    Ok(data)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::checksummed_offset_log::*;
+    use crate::flume_log::FlumeLog;
+    use buffered_offset_reader::OffsetWrite;
+
+    extern crate tempfile;
+    use self::tempfile::tempfile;
+
+    #[test]
+    fn append_and_read() -> Result<(), Error> {
+        let mut log = ChecksummedOffsetLog::<u32>::from_file(tempfile()?)?;
+        let a = log.append(b"abc")?;
+        let b = log.append(b"")?;
+        let c = log.append(b"defgh")?;
+
+        assert_eq!(log.get(a)?, b"abc");
+        assert_eq!(log.get(b)?, b"");
+        assert_eq!(log.get(c)?, b"defgh");
+        let data: Vec<Vec<u8>> = log.iter().map(|e| e.data).collect();
+        assert_eq!(data, &[&b"abc"[..], b"", b"defgh"]);
+
+        log.clear(a)?;
+        assert_eq!(log.get(a)?, &[0; 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn detects_corrupt_data() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut log = ChecksummedOffsetLog::<u32>::from_file(file.try_clone()?)?;
+        let a = log.append(b"abc")?;
+        let b = log.append(b"defgh")?;
+        log.append(b"ij")?;
+
+        // Change one byte in the middle of the second entry's data.
+        file.write_at(b"e", b + 4 + 2)?;
+
+        assert_eq!(log.get(a)?, b"abc");
+        match log.get(b).unwrap_err().downcast::<FlumeOffsetLogError>()? {
+            FlumeOffsetLogError::ChecksumMismatch { offset } => assert_eq!(offset, b),
+            e => panic!("unexpected error: {}", e),
+        }
+        assert_eq!(log.iter().count(), 1);
+
+        // The framing is untouched, so a plain OffsetLog reads it fine.
+        let plain = OffsetLog::<u32>::from_file(file)?;
+        assert_eq!(&plain.get(b)?[..5], b"deegh");
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use crate::flume_log::*;
+use crate::log_entry::LogEntry;
+use crate::offset_log::{FlumeOffsetLogError, OffsetLog, OffsetType};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+// Marks entry data that is stored as is. Zero, so that a cleared entry
+// reads back as zeros.
+const RAW: u8 = 0;
+
+/// A compression scheme for `CompressedOffsetLog`.
+pub trait Codec {
+    /// The byte written before data compressed with this codec. Must not
+    /// be zero, which marks uncompressed data.
+    const MARKER: u8;
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error>;
+}
+
+/// zlib compression, using `flate2`.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Zlib {
+    pub level: flate2::Compression,
+}
+
+impl Codec for Zlib {
+    const MARKER: u8 = 1;
+
+    fn compress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), self.level);
+        encoder.write_all(data)?;
+        Ok(encoder.finish()?)
+    }
+
+    fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        ZlibDecoder::new(data).read_to_end(&mut out)?;
+        Ok(out)
+    }
+}
+
+/// An `OffsetLog` that compresses the data of each entry.
+///
+/// Stored data starts with a marker byte: `Codec::MARKER` if the rest is
+/// compressed, or zero if it was stored as is because compressing didn't
+/// make it smaller. The framing is the same as any other
+/// `OffsetLog<ByteType>`, with the stored length being that of the marker
+/// and compressed data.
+pub struct CompressedOffsetLog<ByteType, C = Zlib> {
+    log: OffsetLog<ByteType>,
+    codec: C,
+}
+
+impl<ByteType: OffsetType, C: Codec + Default> CompressedOffsetLog<ByteType, C> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<CompressedOffsetLog<ByteType, C>, Error> {
+        Ok(CompressedOffsetLog::with_codec(
+            OffsetLog::new(path)?,
+            C::default(),
+        ))
+    }
+
+    pub fn from_file(file: File) -> Result<CompressedOffsetLog<ByteType, C>, Error> {
+        Ok(CompressedOffsetLog::with_codec(
+            OffsetLog::from_file(file)?,
+            C::default(),
+        ))
+    }
+}
+
+impl<ByteType, C: Codec> CompressedOffsetLog<ByteType, C> {
+    pub fn with_codec(log: OffsetLog<ByteType>, codec: C) -> CompressedOffsetLog<ByteType, C> {
+        CompressedOffsetLog { log, codec }
+    }
+
+    pub fn end(&self) -> u64 {
+        self.log.end()
+    }
+
+    /// Iterate over the entries of the log, decompressing each one.
+    /// Iteration stops at the first entry that can't be decompressed.
+    pub fn iter(&self) -> impl Iterator<Item = LogEntry> + '_ {
+        self.log
+            .iter()
+            .map(move |e| {
+                let offset = e.offset;
+                self.decode(offset, &e.data)
+                    .map(|data| LogEntry { offset, data })
+            })
+            .take_while(Result::is_ok)
+            .filter_map(Result::ok)
+    }
+
+    fn decode(&self, offset: u64, stored: &[u8]) -> Result<Vec<u8>, Error> {
+        match stored.split_first() {
+            Some((&RAW, data)) => Ok(data.to_vec()),
+            Some((&marker, data)) if marker == C::MARKER => self.codec.decompress(data),
+            Some((&marker, _)) => {
+                Err(FlumeOffsetLogError::UnknownCompression { offset, marker }.into())
+            }
+            None => Err(FlumeOffsetLogError::CorruptLogFile {}.into()),
+        }
+    }
+}
+
+impl<ByteType, C: Codec> FlumeLog for CompressedOffsetLog<ByteType, C> {
+    fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
+        self.decode(seq_num, &self.log.read(seq_num)?.entry.data)
+    }
+
+    fn latest(&self) -> Option<u64> {
+        self.log.latest()
+    }
+
+    fn append(&mut self, buff: &[u8]) -> Result<u64, Error> {
+        let compressed = self.codec.compress(buff)?;
+        let (marker, data) = if compressed.len() < buff.len() {
+            (C::MARKER, &compressed[..])
+        } else {
+            (RAW, buff)
+        };
+
+        let mut stored = Vec::with_capacity(data.len() + 1);
+        stored.push(marker);
+        stored.extend_from_slice(data);
+        self.log.append(&stored)
+    }
+
+    /// Zero the stored data of an entry. The marker becomes the raw marker,
+    /// so the entry reads back as zeros, as many as were stored.
+    fn clear(&mut self, seq_num: u64) -> Result<(), Error> {
+        self.log.clear(seq_num)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::compressed_offset_log::*;
+    use crate::flume_log::FlumeLog;
+    use buffered_offset_reader::OffsetWrite;
+
+    extern crate tempfile;
+    use self::tempfile::tempfile;
+
+    fn large_json() -> Vec<u8> {
+        let messages: Vec<String> = (0..500)
+            .map(|i| {
+                format!(
+                    "{{\"key\":\"%msg{}.sha256\",\"value\":{{\"author\":\"@feed.ed25519\",\"sequence\":{},\"content\":{{\"type\":\"post\",\"text\":\"hello number {}\"}}}}}}",
+                    i, i, i
+                )
+            })
+            .collect();
+        format!("[{}]", messages.join(",")).into_bytes()
+    }
+
+    #[test]
+    fn round_trip_large_json() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut log = CompressedOffsetLog::<u32>::from_file(file.try_clone()?)?;
+        let doc = large_json();
+        let a = log.append(&doc)?;
+        let b = log.append(b"{}")?;
+
+        assert_eq!(log.get(a)?, doc);
+        assert_eq!(log.get(b)?, b"{}");
+        let data: Vec<Vec<u8>> = log.iter().map(|e| e.data).collect();
+        assert_eq!(data, [doc.clone(), b"{}".to_vec()]);
+
+        // The document is stored compressed, and the tiny entry as is.
+        let plain = OffsetLog::<u32>::from_file(file)?;
+        let stored = plain.get(a)?;
+        assert_eq!(stored[0], Zlib::MARKER);
+        assert!(stored.len() * 5 < doc.len());
+        assert_eq!(plain.get(b)?, b"\0{}");
+        Ok(())
+    }
+
+    #[test]
+    fn clear_and_unknown_marker() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut log = CompressedOffsetLog::<u32>::from_file(file.try_clone()?)?;
+        let a = log.append(&large_json())?;
+        let b = log.append(b"abc")?;
+        let stored_size = OffsetLog::<u32>::from_file(file.try_clone()?)?
+            .get(a)?
+            .len();
+
+        log.clear(a)?;
+        assert_eq!(log.get(a)?, vec![0; stored_size - 1]);
+
+        file.write_at(&[7], b + 4)?;
+        match log.get(b).unwrap_err().downcast::<FlumeOffsetLogError>()? {
+            FlumeOffsetLogError::UnknownCompression { offset, marker } => {
+                assert_eq!((offset, marker), (b, 7))
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        Ok(())
+    }
+}
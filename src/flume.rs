@@ -0,0 +1,186 @@
+use crate::flume_log::*;
+use crate::flume_view::{FlumeView, FlumeViewError};
+use crate::iter_at_offset::IterAtOffset;
+use crate::offset_log::OffsetLog;
+
+/// An `OffsetLog` and the views that are kept in sync with it.
+///
+/// Every entry appended through `append` is passed on to each view. Views
+/// that have fallen behind the log, for example after a restart, are
+/// brought up to date with `update_views`.
+pub struct Flume<ByteType> {
+    log: OffsetLog<ByteType>,
+    views: Vec<Box<dyn FlumeView>>,
+}
+
+impl<ByteType> Flume<ByteType> {
+    pub fn new(log: OffsetLog<ByteType>) -> Flume<ByteType> {
+        Flume {
+            log,
+            views: Vec::new(),
+        }
+    }
+
+    pub fn add_view(&mut self, view: Box<dyn FlumeView>) {
+        self.views.push(view);
+    }
+
+    pub fn log(&self) -> &OffsetLog<ByteType> {
+        &self.log
+    }
+
+    pub fn views(&self) -> &[Box<dyn FlumeView>] {
+        &self.views
+    }
+
+    /// Append an entry to the log, then to every view. Views are given the
+    /// entry as `get` would return it, the same as in `update_views`.
+    pub fn append(&mut self, item: &[u8]) -> Result<Sequence, Error> {
+        let seq = self.log.append(item)?;
+        let item = self.log.read_back(item)?;
+        for view in self.views.iter_mut() {
+            append_to_view(view.as_mut(), seq, &item)?;
+        }
+        Ok(seq)
+    }
+
+    /// Feed each view the entries that come after its `latest()`, up to the
+    /// end of the log.
+    pub fn update_views(&mut self) -> Result<(), Error> {
+        for view in self.views.iter_mut() {
+            let start = match view.latest() {
+                Some(seq) => self.log.read(seq)?.next,
+                None => 0,
+            };
+            for entry in self.log.iter_at_offset(start) {
+                let item = self.log.inverse_body(entry.data)?;
+                append_to_view(view.as_mut(), entry.offset, &item)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Items a view chooses to skip don't stop the other views or later entries.
+fn append_to_view(view: &mut dyn FlumeView, seq: Sequence, item: &[u8]) -> Result<(), Error> {
+    match view.append(seq, item) {
+        Err(e) if e.downcast_ref::<FlumeViewError>().is_some() => Ok(()),
+        r => r,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume::*;
+    use crate::flume_view::FlumeView;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    extern crate tempfile;
+    use self::tempfile::tempfile;
+
+    type Seen = Rc<RefCell<Vec<(Sequence, Vec<u8>)>>>;
+
+    // Records what it's given, and skips anything that isn't valid UTF-8.
+    struct RecordingView {
+        seen: Seen,
+        latest: Option<Sequence>,
+    }
+
+    impl FlumeView for RecordingView {
+        fn append(&mut self, seq: Sequence, item: &[u8]) -> Result<(), Error> {
+            self.latest = Some(seq);
+            if std::str::from_utf8(item).is_err() {
+                let reason = "not utf-8".to_string();
+                return Err(FlumeViewError::SkippedItem { seq, reason }.into());
+            }
+            self.seen.borrow_mut().push((seq, item.to_vec()));
+            Ok(())
+        }
+
+        fn latest(&self) -> Option<Sequence> {
+            self.latest
+        }
+    }
+
+    fn recording_view() -> (Box<dyn FlumeView>, Seen) {
+        let seen = Seen::default();
+        let view = RecordingView {
+            seen: seen.clone(),
+            latest: None,
+        };
+        (Box::new(view), seen)
+    }
+
+    #[test]
+    fn append_feeds_views() -> Result<(), Error> {
+        let mut flume = Flume::new(OffsetLog::<u32>::from_file(tempfile()?)?);
+        let (a, a_seen) = recording_view();
+        let (b, b_seen) = recording_view();
+        flume.add_view(a);
+        flume.add_view(b);
+
+        let first = flume.append(b"hello")?;
+        flume.append(&[0xff])?;
+        let third = flume.append(b"world")?;
+
+        let expected = vec![(first, b"hello".to_vec()), (third, b"world".to_vec())];
+        assert_eq!(*a_seen.borrow(), expected);
+        assert_eq!(*b_seen.borrow(), expected);
+        assert_eq!(flume.views()[0].latest(), Some(third));
+        assert_eq!(flume.log().get(third)?, b"world");
+        Ok(())
+    }
+
+    #[test]
+    fn update_views_catches_up() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut flume = Flume::new(OffsetLog::<u32>::from_file(file.try_clone()?)?);
+        let (early, early_seen) = recording_view();
+        flume.add_view(early);
+        let a = flume.append(b"a")?;
+        let b = flume.append(b"b")?;
+
+        // Reopen with the early view, which has seen everything, and a new
+        // view which has seen nothing.
+        let views = std::mem::take(&mut flume.views);
+        let mut flume = Flume::new(OffsetLog::<u32>::from_file(file)?);
+        for view in views {
+            flume.add_view(view);
+        }
+        let (late, late_seen) = recording_view();
+        flume.add_view(late);
+        flume.log.append(b"c")?;
+        let c = flume.log().latest().unwrap();
+
+        flume.update_views()?;
+        let expected = vec![(a, b"a".to_vec()), (b, b"b".to_vec()), (c, b"c".to_vec())];
+        assert_eq!(*early_seen.borrow(), expected);
+        assert_eq!(*late_seen.borrow(), expected);
+
+        flume.update_views()?;
+        assert_eq!(late_seen.borrow().len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn views_see_entries_as_read_back() -> Result<(), Error> {
+        let mut log = OffsetLog::<u32>::from_file(tempfile()?)?;
+        log.set_body_transform(Box::new(|b| Ok(b.to_ascii_uppercase())));
+        log.set_body_inverse(Box::new(|b| Ok([b, b"!"].concat())));
+        let mut flume = Flume::new(log);
+        let (early, early_seen) = recording_view();
+        flume.add_view(early);
+        let a = flume.append(b"abc")?;
+
+        let (late, late_seen) = recording_view();
+        flume.add_view(late);
+        flume.update_views()?;
+
+        let expected = vec![(a, b"ABC!".to_vec())];
+        assert_eq!(*early_seen.borrow(), expected);
+        assert_eq!(*late_seen.borrow(), expected);
+        assert_eq!(flume.log().get(a)?, b"ABC!");
+        Ok(())
+    }
+}
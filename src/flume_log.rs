@@ -18,7 +18,7 @@ pub type Sequence = u64;
 
 pub trait FlumeLog {
     fn get(&self, seq: Sequence) -> Result<Vec<u8>, Error>;
-    fn clear(&mut self, seq: Sequence);
+    fn clear(&mut self, seq: Sequence) -> Result<(), Error>;
     fn latest(&self) -> Option<Sequence>;
     fn append(&mut self, buff: &[u8]) -> Result<Sequence, Error>;
 }
@@ -1,6 +1,100 @@
 pub use crate::flume_log::Sequence;
+use failure::Error;
+
+#[derive(Debug, Fail)]
+pub enum FlumeViewError {
+    /// The view couldn't make sense of an item and left it out. Unlike
+    /// other errors this doesn't mean the view is broken, so callers can
+    /// carry on appending.
+    #[fail(display = "Skipped item at sequence {}: {}", seq, reason)]
+    SkippedItem { seq: Sequence, reason: String },
+}
 
 pub trait FlumeView {
-    fn append(&mut self, seq: Sequence, item: &[u8]);
-    fn latest(&self) -> Sequence;
+    fn append(&mut self, seq: Sequence, item: &[u8]) -> Result<(), Error>;
+    /// The last sequence the view has processed, or `None` if it hasn't
+    /// processed any.
+    fn latest(&self) -> Option<Sequence>;
+    /// The bytes the view holds for `seq`, if it keeps them. Views that
+    /// only compute something from their items return `None`.
+    fn get(&self, _seq: Sequence) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_view::*;
+    use serde_json::{from_slice, Value};
+
+    #[derive(Default)]
+    struct SumView {
+        sum: u64,
+        latest: Option<Sequence>,
+    }
+
+    impl FlumeView for SumView {
+        fn append(&mut self, seq: Sequence, item: &[u8]) -> Result<(), Error> {
+            self.latest = Some(seq);
+            let value: Value = from_slice(item).map_err(|e| FlumeViewError::SkippedItem {
+                seq,
+                reason: e.to_string(),
+            })?;
+            self.sum += value["value"].as_u64().unwrap_or(0);
+            Ok(())
+        }
+
+        fn latest(&self) -> Option<Sequence> {
+            self.latest
+        }
+    }
+
+    // Keeps every item, so it can serve them back.
+    #[derive(Default)]
+    struct StoreView {
+        items: std::collections::BTreeMap<Sequence, Vec<u8>>,
+    }
+
+    impl FlumeView for StoreView {
+        fn append(&mut self, seq: Sequence, item: &[u8]) -> Result<(), Error> {
+            self.items.insert(seq, item.to_vec());
+            Ok(())
+        }
+
+        fn latest(&self) -> Option<Sequence> {
+            self.items.keys().next_back().cloned()
+        }
+
+        fn get(&self, seq: Sequence) -> Option<Vec<u8>> {
+            self.items.get(&seq).cloned()
+        }
+    }
+
+    #[test]
+    fn get() {
+        let mut sum = SumView::default();
+        let mut store = StoreView::default();
+        for view in [&mut sum as &mut dyn FlumeView, &mut store] {
+            view.append(0, b"{\"value\": 1}").unwrap();
+            view.append(20, b"{\"value\": 2}").unwrap();
+        }
+
+        assert_eq!(sum.get(20), None);
+        assert_eq!(store.get(20), Some(b"{\"value\": 2}".to_vec()));
+        assert_eq!(store.get(10), None);
+    }
+
+    #[test]
+    fn skipped_items_are_distinguishable() {
+        let mut view = SumView::default();
+        view.append(0, b"{\"value\": 1}").unwrap();
+
+        let err = view.append(1, b"not json").unwrap_err();
+        match err.downcast::<FlumeViewError>().unwrap() {
+            FlumeViewError::SkippedItem { seq, .. } => assert_eq!(seq, 1),
+        }
+
+        view.append(2, b"{\"value\": 2}").unwrap();
+        assert_eq!((view.sum, view.latest()), (3, Some(2)));
+    }
 }
@@ -34,6 +34,7 @@ pub enum GoFlumeOffsetLogError {
     DecodeBufferSizeTooSmall {},
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Default, Deserialize)]
 struct GoMsgPackKey<'a> {
     #[serde(rename = "Algo")]
@@ -52,6 +53,7 @@ impl<'a> GoMsgPackKey<'a> {
     }
 }
 
+#[allow(dead_code)]
 #[derive(Debug, Default, Deserialize)]
 struct GoMsgPackData<'a> {
     #[serde(rename = "Raw_")]
@@ -98,6 +100,7 @@ impl GoOffsetLog {
             .read(true)
             .write(true)
             .create(true)
+            .truncate(false)
             .open(data_file_path)?;
 
         GoOffsetLog::from_files(data_file)
@@ -215,8 +218,7 @@ where
 {
     // Entry is [payload size: u64, payload ]
 
-    let mut buf = Vec::with_capacity(frame.data_size);
-    unsafe { buf.set_len(frame.data_size) };
+    let mut buf = vec![0; frame.data_size];
 
     let n = read_at(&mut buf, frame.data_start())?;
     if n < frame.data_size {
@@ -247,7 +249,7 @@ where
 
     let ssb_message = json!({
         "key": cbor.key.to_legacy_string(),
-        "value": serde_json::from_str::<Value>(&cbor.raw)?,
+        "value": serde_json::from_str::<Value>(cbor.raw)?,
         "timestamp": timestamp as u64
     });
 
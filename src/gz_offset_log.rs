@@ -0,0 +1,158 @@
+use crate::flume_log::*;
+use crate::log_entry::LogEntry;
+use crate::offset_log::{size_of_frame_tail, validate_entry, FlumeOffsetLogError};
+use byteorder::{BigEndian, ReadBytesExt};
+use flate2::read::GzDecoder;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read};
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+/// A read-only `OffsetLog` that has been gzipped as a whole file.
+///
+/// A gzip stream can only be read from the start, so entries are served by
+/// decompressing sequentially. `iter` is the efficient way in; `get` has to
+/// scan from the start of the log.
+pub struct GzOffsetLog<ByteType> {
+    path: PathBuf,
+    byte_type: PhantomData<ByteType>,
+}
+
+impl<ByteType> GzOffsetLog<ByteType> {
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<GzOffsetLog<ByteType>, Error> {
+        // Fail early if the file is missing or isn't gzip.
+        if GzDecoder::new(File::open(&path)?).header().is_none() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a gzip file").into());
+        }
+
+        Ok(GzOffsetLog {
+            path: path.as_ref().to_path_buf(),
+            byte_type: PhantomData,
+        })
+    }
+
+    /// Iterate over the entries of the log, decompressing as it goes. Each
+    /// call starts a new pass over the file.
+    pub fn iter(&self) -> Result<GzOffsetLogIter<ByteType>, Error> {
+        let file = File::open(&self.path)?;
+        Ok(GzOffsetLogIter {
+            reader: GzDecoder::new(BufReader::new(file)),
+            offset: 0,
+            byte_type: PhantomData,
+        })
+    }
+
+    /// Get the entry at `seq_num`, by scanning the log up to it.
+    pub fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
+        self.iter()?
+            .take_while(|e| e.offset <= seq_num)
+            .find(|e| e.offset == seq_num)
+            .map(|e| e.data)
+            .ok_or_else(|| FlumeLogError::SequenceNotFound { sequence: seq_num }.into())
+    }
+}
+
+pub struct GzOffsetLogIter<ByteType> {
+    reader: GzDecoder<BufReader<File>>,
+    offset: u64,
+    byte_type: PhantomData<ByteType>,
+}
+
+impl<ByteType> GzOffsetLogIter<ByteType> {
+    fn read_entry(&mut self) -> Result<LogEntry, Error> {
+        let data_size = self.reader.read_u32::<BigEndian>()? as usize;
+        let to_read = data_size + size_of_frame_tail::<ByteType>();
+
+        // The buffer grows as data is decompressed, so a corrupt size can't
+        // allocate more than the rest of the stream holds.
+        let mut buf = Vec::new();
+        (&mut self.reader)
+            .take(to_read as u64)
+            .read_to_end(&mut buf)?;
+        if buf.len() < to_read {
+            return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        }
+        let next = validate_entry::<ByteType>(self.offset, data_size, &buf)?;
+
+        buf.truncate(data_size);
+        let entry = LogEntry {
+            offset: self.offset,
+            data: buf,
+        };
+        self.offset = next;
+        Ok(entry)
+    }
+}
+
+impl<ByteType> Iterator for GzOffsetLogIter<ByteType> {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_entry().ok()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::FlumeLog;
+    use crate::gz_offset_log::*;
+    use crate::offset_log::OffsetLog;
+    use flate2::write::GzEncoder;
+    use flate2::Compression;
+    use std::io::Write;
+
+    extern crate tempfile;
+    use self::tempfile::tempdir;
+
+    #[test]
+    fn iterate_gzipped_log() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log.offset");
+        let gz_path = dir.path().join("log.offset.gz");
+
+        let mut log = OffsetLog::<u32>::new(&path)?;
+        for i in 0..100u32 {
+            log.append(format!("{{\"value\":{}}}", i).as_bytes())?;
+        }
+
+        let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+        io::copy(&mut File::open(&path)?, &mut encoder)?;
+        encoder.finish()?;
+
+        let gz = OffsetLog::<u32>::open_gz_read_only(&gz_path)?;
+        let original: Vec<(u64, Vec<u8>)> = log.iter().map(|e| (e.offset, e.data)).collect();
+        let archived: Vec<(u64, Vec<u8>)> = gz.iter()?.map(|e| (e.offset, e.data)).collect();
+        assert_eq!(archived.len(), 100);
+        assert_eq!(archived, original);
+
+        let last = log.latest().unwrap();
+        assert_eq!(gz.get(last)?, log.get(last)?);
+        assert!(gz.get(last + 1).is_err());
+
+        assert!(GzOffsetLog::<u32>::open_read_only(&path).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn corrupt_size_ends_iteration() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let path = dir.path().join("log.offset");
+        let gz_path = dir.path().join("log.offset.gz");
+
+        OffsetLog::<u32>::new(&path)?.append(b"abc")?;
+        let mut bytes = std::fs::read(&path)?;
+        // A second frame that claims to be far bigger than the stream.
+        bytes.extend_from_slice(&u32::MAX.to_be_bytes());
+        bytes.extend_from_slice(b"defgh");
+
+        let mut encoder = GzEncoder::new(File::create(&gz_path)?, Compression::default());
+        encoder.write_all(&bytes)?;
+        encoder.finish()?;
+
+        let gz = GzOffsetLog::<u32>::open_read_only(&gz_path)?;
+        let data: Vec<Vec<u8>> = gz.iter()?.map(|e| e.data).collect();
+        assert_eq!(data, [b"abc"]);
+        Ok(())
+    }
+}
@@ -0,0 +1,106 @@
+use crate::log_entry::LogEntry;
+use serde::de::IgnoredAny;
+use std::cmp::min;
+use std::io;
+use std::io::Read;
+
+/// Adapts an iterator of log entries into a `Read` of newline-delimited
+/// JSON. Each entry's data is followed by a `\n`. Entries are pulled from
+/// the iterator only as they're needed.
+///
+/// By default every entry is emitted, whether or not it is valid JSON.
+/// Use `skip_invalid` to drop entries that don't parse.
+pub struct JsonLinesReader<I> {
+    entries: I,
+    skip_invalid: bool,
+    line: Vec<u8>,
+    pos: usize,
+}
+
+impl<I: Iterator<Item = LogEntry>> JsonLinesReader<I> {
+    pub fn new(entries: I) -> JsonLinesReader<I> {
+        JsonLinesReader {
+            entries,
+            skip_invalid: false,
+            line: Vec::new(),
+            pos: 0,
+        }
+    }
+
+    pub fn skip_invalid(mut self, skip: bool) -> JsonLinesReader<I> {
+        self.skip_invalid = skip;
+        self
+    }
+
+    // Loads the next line into `self.line`. Returns false at the end of the log.
+    fn next_line(&mut self) -> bool {
+        let skip_invalid = self.skip_invalid;
+        let entry = self
+            .entries
+            .find(|e| !skip_invalid || serde_json::from_slice::<IgnoredAny>(&e.data).is_ok());
+
+        match entry {
+            Some(e) => {
+                self.line = e.data;
+                self.line.push(b'\n');
+                self.pos = 0;
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+impl<I: Iterator<Item = LogEntry>> Read for JsonLinesReader<I> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.pos == self.line.len() && !self.next_line() {
+            return Ok(0);
+        }
+
+        let n = min(buf.len(), self.line.len() - self.pos);
+        buf[..n].copy_from_slice(&self.line[self.pos..self.pos + n]);
+        self.pos += n;
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::json_lines_reader::*;
+
+    fn entries(data: &[&[u8]]) -> impl Iterator<Item = LogEntry> {
+        data.iter()
+            .enumerate()
+            .map(|(i, d)| LogEntry {
+                offset: i as u64,
+                data: d.to_vec(),
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+    }
+
+    #[test]
+    fn skip_invalid() {
+        let mut out = String::new();
+        JsonLinesReader::new(entries(&[b"{}", b"not json", b"[1]"]))
+            .skip_invalid(true)
+            .read_to_string(&mut out)
+            .unwrap();
+        assert_eq!(out, "{}\n[1]\n");
+    }
+
+    #[test]
+    fn small_reads() {
+        let mut reader = JsonLinesReader::new(entries(&[b"{\"a\":1}", b"not json"]));
+        let mut out = Vec::new();
+        let mut buf = [0; 3];
+        loop {
+            let n = reader.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(out, b"{\"a\":1}\nnot json\n");
+    }
+}
@@ -2,31 +2,64 @@
 //!# flumedb
 //!
 //!
+#![allow(non_local_definitions)]
+#[cfg(feature = "bincode")]
+extern crate bincode;
 extern crate bidir_iter;
 extern crate buffered_offset_reader;
 extern crate byteorder;
 extern crate bytes;
 #[macro_use]
 extern crate failure;
+extern crate flate2;
 extern crate log;
+extern crate memmap;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde_json;
 extern crate serde_cbor;
+extern crate ssb_crypto;
 extern crate ssb_multiformats;
+#[cfg(feature = "async")]
+extern crate tokio;
 
 
+#[cfg(feature = "async")]
+pub mod async_offset_log;
+pub mod checksummed_offset_log;
+pub mod compressed_offset_log;
+mod crc32;
+pub mod flume;
 pub mod flume_log;
 pub mod flume_view;
 pub mod go_offset_log;
+pub mod gz_offset_log;
 pub mod iter_at_offset;
+pub mod json_lines_reader;
 pub mod log_entry;
+pub mod map_view;
 pub mod mem_log;
+pub mod mmap_offset_log;
 pub mod offset_log;
+pub mod segmented_offset_log;
+pub mod typed_offset_log;
+pub mod varint_offset_log;
 
+#[cfg(feature = "async")]
+pub use async_offset_log::{AsyncFlumeLog, AsyncOffsetLog};
+pub use checksummed_offset_log::ChecksummedOffsetLog;
+pub use compressed_offset_log::CompressedOffsetLog;
+pub use flume::Flume;
 pub use flume_log::*;
 pub use flume_view::*;
+pub use gz_offset_log::GzOffsetLog;
 pub use iter_at_offset::*;
+pub use json_lines_reader::*;
+pub use map_view::MapView;
 pub use mem_log::*;
+pub use mmap_offset_log::MmapOffsetLog;
 pub use offset_log::*;
+pub use segmented_offset_log::*;
+pub use typed_offset_log::TypedOffsetLog;
+pub use varint_offset_log::VarintOffsetLog;
@@ -3,3 +3,39 @@ pub struct LogEntry {
     pub offset: u64,
     pub data: Vec<u8>,
 }
+
+/// An entry from a log written with `OffsetLog::append_tagged`, where the
+/// first byte of the stored data is a tag.
+///
+/// Nothing in the log marks an entry as tagged, so the tagged API is only
+/// valid on logs written entirely with `append_tagged`. An entry written
+/// with `append` is read with its first data byte as the tag.
+#[derive(Debug)]
+pub struct TaggedLogEntry {
+    pub offset: u64,
+    pub tag: u8,
+    // The data as stored, tag byte included, so that splitting the tag off
+    // doesn't have to move the rest of the data.
+    stored: Vec<u8>,
+}
+
+impl TaggedLogEntry {
+    /// The entry data, without the tag.
+    pub fn data(&self) -> &[u8] {
+        &self.stored[1..]
+    }
+}
+
+impl LogEntry {
+    /// Read the first byte of the data as a tag. Returns `None` if the
+    /// entry is empty, and so can't have been written with a tag. See
+    /// `TaggedLogEntry` for when this is valid.
+    pub fn into_tagged(self) -> Option<TaggedLogEntry> {
+        let tag = *self.data.first()?;
+        Some(TaggedLogEntry {
+            offset: self.offset,
+            tag,
+            stored: self.data,
+        })
+    }
+}
@@ -0,0 +1,75 @@
+use crate::flume_view::{FlumeView, FlumeViewError, Sequence};
+use failure::Error;
+use std::collections::HashMap;
+
+// The part of an SSB message, as stored in the log, that views need.
+#[derive(Deserialize)]
+struct KeyedMessage {
+    key: String,
+}
+
+/// Parse the `key` (the message id) out of an SSB message in its log form,
+/// `{"key": ..., "value": ..., "timestamp": ...}`.
+pub fn parse_message_key(item: &[u8]) -> Result<String, serde_json::Error> {
+    serde_json::from_slice::<KeyedMessage>(item).map(|m| m.key)
+}
+
+/// A `FlumeView` that maps each SSB message's key to its sequence in the
+/// log, held in memory.
+///
+/// Items that aren't SSB messages are skipped with `SkippedItem`.
+#[derive(Default)]
+pub struct MapView {
+    seqs: HashMap<String, Sequence>,
+    latest: Option<Sequence>,
+}
+
+impl MapView {
+    pub fn new() -> MapView {
+        MapView::default()
+    }
+
+    pub fn get_seq_by_key(&self, key: &str) -> Option<Sequence> {
+        self.seqs.get(key).cloned()
+    }
+}
+
+impl FlumeView for MapView {
+    fn append(&mut self, seq: Sequence, item: &[u8]) -> Result<(), Error> {
+        self.latest = Some(seq);
+        let key = parse_message_key(item).map_err(|e| FlumeViewError::SkippedItem {
+            seq,
+            reason: e.to_string(),
+        })?;
+        self.seqs.insert(key, seq);
+        Ok(())
+    }
+
+    fn latest(&self) -> Option<Sequence> {
+        self.latest
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::map_view::*;
+
+    const KEY: &str = "%KKPLj1tWfuVhCvgJz2hG/nIsVzmBRzUJaqHv+sb+n1c=.sha256";
+
+    #[test]
+    fn get_seq_by_key() {
+        let msg = format!(
+            "{{\"key\":\"{}\",\"value\":{{\"content\":{{\"type\":\"post\"}}}},\"timestamp\":1}}",
+            KEY
+        );
+        let mut view = MapView::new();
+        view.append(0, msg.as_bytes()).unwrap();
+        assert_eq!(view.get_seq_by_key(KEY), Some(0));
+        assert_eq!(view.get_seq_by_key("%missing.sha256"), None);
+
+        let err = view.append(100, b"{\"value\": 1}").unwrap_err();
+        assert!(err.downcast_ref::<FlumeViewError>().is_some());
+        assert_eq!(view.latest(), Some(100));
+        assert_eq!(view.get_seq_by_key(KEY), Some(0));
+    }
+}
@@ -2,6 +2,7 @@ use crate::flume_log::*;
 
 use std::iter::IntoIterator;
 
+#[derive(Default)]
 pub struct MemLog {
     log: Vec<Vec<u8>>,
 }
@@ -11,20 +12,31 @@ impl MemLog {
         let log = Vec::new();
         MemLog { log }
     }
+
+    /// Append a batch of entries, returning their sequence numbers.
+    pub fn append_batch<T: AsRef<[u8]>>(&mut self, buffs: &[T]) -> Result<Vec<u64>, Error> {
+        buffs.iter().map(|b| self.append(b.as_ref())).collect()
+    }
 }
 
 impl FlumeLog for MemLog {
     fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
         self.log
             .get(seq_num as usize)
-            .map(|slice| slice.clone())
+            .cloned()
             .ok_or(FlumeLogError::SequenceNotFound { sequence: seq_num }.into())
     }
-    fn clear(&mut self, seq: u64) {
-        self.log[seq as usize] = Vec::new();
+    fn clear(&mut self, seq: u64) -> Result<(), Error> {
+        let entry = self
+            .log
+            .get_mut(seq as usize)
+            .ok_or(FlumeLogError::SequenceNotFound { sequence: seq })?;
+        // Like `OffsetLog`, the entry keeps its length and is zeroed.
+        entry.iter_mut().for_each(|b| *b = 0);
+        Ok(())
     }
     fn latest(&self) -> Option<u64> {
-        if self.log.len() == 0 {
+        if self.log.is_empty() {
             None
         } else {
             Some(self.log.len() as u64 - 1)
@@ -61,20 +73,21 @@ mod tests {
 
         match log.get(seq0) {
             Ok(result) => assert_eq!(String::from_utf8_lossy(&result), "Hello"),
-            _ => assert!(false),
+            _ => panic!(),
         }
     }
     #[test]
     fn clear() {
         let mut log = MemLog::new();
         let seq0 = log.append("Hello".as_bytes()).unwrap();
-        log.clear(seq0);
+        log.clear(seq0).unwrap();
         match log.get(seq0) {
             Ok(result) => {
-                assert_eq!(result.len(), 0);
+                assert_eq!(result, vec![0; 5]);
             }
-            _ => assert!(false),
+            _ => panic!(),
         }
+        assert!(log.clear(seq0 + 1).is_err());
     }
     #[test]
     fn iter() {
@@ -99,7 +112,16 @@ mod tests {
 
         match log.get(seq0) {
             Ok(result) => assert_eq!(String::from_utf8_lossy(&result), "Hello"),
-            _ => assert!(false),
+            _ => panic!(),
         }
     }
+    #[test]
+    fn append_batch() {
+        let mut log = MemLog::new();
+        log.append(b"a").unwrap();
+        let seqs = log.append_batch(&[&b"b"[..], b"cd"]).unwrap();
+        assert_eq!(seqs, vec![1, 2]);
+        assert_eq!(log.get(2).unwrap(), b"cd");
+        assert_eq!(log.latest(), Some(2));
+    }
 }
@@ -0,0 +1,264 @@
+use crate::flume_log::*;
+use crate::offset_log::{read_slice, Forward, OffsetLog, OffsetLogIter, OffsetType};
+use memmap::{Mmap, MmapOptions};
+use std::borrow::Cow;
+use std::fs::File;
+use std::path::Path;
+
+// The smallest map made of a log. Maps grow by doubling from here.
+const MIN_MAP_SIZE: u64 = 64 * 1024;
+
+/// An `OffsetLog` that serves reads from a memory map of the log file, so a
+/// `get` is a bounds check and a slice rather than a `pread` syscall.
+///
+/// Appends go through the underlying `OffsetLog`, so a write buffer set on
+/// it still applies; buffered appends are written out when they're read.
+/// The map covers more than the file, and is only remapped, at double the
+/// size, when the log grows past it. The map assumes the file is only ever
+/// appended to; another process truncating it would make reads from the map
+/// fault.
+pub struct MmapOffsetLog<ByteType> {
+    log: OffsetLog<ByteType>,
+    map: Option<Mmap>,
+}
+
+impl<ByteType> MmapOffsetLog<ByteType> {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<MmapOffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        MmapOffsetLog::from_offset_log(OffsetLog::new(path)?)
+    }
+
+    pub fn from_file(file: File) -> Result<MmapOffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        MmapOffsetLog::from_offset_log(OffsetLog::from_file(file)?)
+    }
+
+    pub fn from_offset_log(log: OffsetLog<ByteType>) -> Result<MmapOffsetLog<ByteType>, Error> {
+        let mut log = MmapOffsetLog { log, map: None };
+        log.grow_map()?;
+        Ok(log)
+    }
+
+    fn map_len(&self) -> u64 {
+        self.map.as_ref().map_or(0, |m| m.len() as u64)
+    }
+
+    // Remap the file if the log has grown past the end of the map.
+    fn grow_map(&mut self) -> Result<(), Error> {
+        if self.log.end() <= self.map_len() {
+            return Ok(());
+        }
+        let len = self.log.end().next_power_of_two().max(MIN_MAP_SIZE);
+        // Safety: the log file is only ever appended to, so the mapped range
+        // that's read, up to the end of the log, stays backed by the file for
+        // the life of the map. The rest of the map, past the end of the file,
+        // is never read.
+        let map = unsafe { MmapOptions::new().len(len as usize).map(&self.log.file)? };
+        self.map = Some(map);
+        Ok(())
+    }
+
+    // The mapped bytes of the log. Buffered appends are written out first,
+    // as the map only sees what's in the file.
+    fn mapped(&self) -> Result<&[u8], Error> {
+        self.log.flush_writes()?;
+        let map = self.map.as_ref().map_or(&[][..], |m| &m[..]);
+        Ok(&map[..self.log.end() as usize])
+    }
+
+    /// Borrow the data of the entry at `offset` straight from the map. This
+    /// is the data as it's stored, so any body inverse isn't applied.
+    pub fn get_slice(&self, offset: u64) -> Result<&[u8], Error> {
+        read_slice::<ByteType>(offset, self.mapped()?)
+    }
+
+    /// Like `get`, but borrows the data from the map rather than copying
+    /// it. With a body inverse set the inverse's output is returned, which
+    /// is owned.
+    pub fn get_cow(&self, seq_num: u64) -> Result<Cow<'_, [u8]>, Error> {
+        let data = self.get_slice(seq_num)?;
+        if self.log.has_body_inverse() {
+            return self.log.inverse_body(data.to_vec()).map(Cow::Owned);
+        }
+        Ok(Cow::Borrowed(data))
+    }
+
+    pub fn end(&self) -> u64 {
+        self.log.end()
+    }
+
+    pub fn iter(&self) -> Forward<OffsetLogIter<ByteType>> {
+        self.log.iter()
+    }
+}
+
+impl<ByteType> FlumeLog for MmapOffsetLog<ByteType> {
+    fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
+        self.log.inverse_body(self.get_slice(seq_num)?.to_vec())
+    }
+
+    fn latest(&self) -> Option<u64> {
+        self.log.latest()
+    }
+
+    fn append(&mut self, buff: &[u8]) -> Result<u64, Error> {
+        let offset = self.log.append(buff)?;
+        self.grow_map()?;
+        Ok(offset)
+    }
+
+    fn clear(&mut self, seq_num: u64) -> Result<(), Error> {
+        self.log.clear(seq_num)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::FlumeLog;
+    use crate::mmap_offset_log::*;
+
+    extern crate tempfile;
+    use self::tempfile::tempfile;
+
+    #[test]
+    fn append_and_get() -> Result<(), Error> {
+        let mut log = MmapOffsetLog::<u32>::from_file(tempfile()?)?;
+        assert!(log.get(0).is_err());
+
+        let a = log.append(b"abc")?;
+        let b = log.append(b"defgh")?;
+        assert_eq!(log.get_slice(a)?, b"abc");
+        assert_eq!(log.get(b)?, b"defgh");
+        assert_eq!(log.latest(), Some(b));
+        assert!(log.get(b + 1).is_err());
+        assert!(log.get(log.end()).is_err());
+
+        log.clear(a)?;
+        assert_eq!(log.get(a)?, &[0; 3]);
+        Ok(())
+    }
+
+    #[test]
+    fn buffered_inner_log() -> Result<(), Error> {
+        let mut inner = OffsetLog::<u32>::from_file(tempfile()?)?;
+        inner.set_write_buffer_capacity(1024)?;
+        inner.append(b"abc")?;
+        let mut log = MmapOffsetLog::from_offset_log(inner)?;
+
+        let b = log.append(b"defgh")?;
+        assert_eq!(log.get(0)?, b"abc");
+        assert_eq!(log.get_slice(b)?, b"defgh");
+        assert_eq!(log.latest(), Some(b));
+        Ok(())
+    }
+
+    #[test]
+    fn appends_stay_buffered() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut inner = OffsetLog::<u32>::from_file(file.try_clone()?)?;
+        inner.set_write_buffer_capacity(1024)?;
+        let mut log = MmapOffsetLog::from_offset_log(inner)?;
+
+        let a = log.append(b"abc")?;
+        let b = log.append(b"defgh")?;
+        assert_eq!(file.metadata()?.len(), 0);
+        assert_eq!(log.get(a)?, b"abc");
+        assert_eq!(file.metadata()?.len(), log.end());
+        assert_eq!(log.get_slice(b)?, b"defgh");
+        Ok(())
+    }
+
+    #[test]
+    fn map_grows_with_the_log() -> Result<(), Error> {
+        let mut log = MmapOffsetLog::<u32>::from_file(tempfile()?)?;
+        let mut offsets = Vec::new();
+        for i in 0..300u32 {
+            offsets.push(log.append(&vec![i as u8; 1000])?);
+            assert_eq!(log.get(offsets[0])?, vec![0; 1000]);
+        }
+        assert!(log.map_len() >= log.end());
+        assert!(log.map_len() < 2 * log.end());
+        for (i, &offset) in offsets.iter().enumerate() {
+            assert_eq!(log.get_slice(offset)?, &vec![i as u8; 1000][..]);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn get_cow_matches_offset_log() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut log = MmapOffsetLog::<u32>::from_file(file.try_clone()?)?;
+        let offsets = (0..20u8)
+            .map(|i| log.append(&vec![i; i as usize]))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let plain = OffsetLog::<u32>::from_file(file.try_clone()?)?;
+        for &offset in offsets.iter() {
+            let owned = plain.get_cow(offset)?;
+            let borrowed = log.get_cow(offset)?;
+            assert!(matches!(owned, Cow::Owned(_)));
+            assert!(matches!(borrowed, Cow::Borrowed(_)));
+            assert_eq!(owned, borrowed);
+        }
+
+        // A body inverse has to produce new data, so it can't be borrowed.
+        let mut inner = OffsetLog::<u32>::from_file(file)?;
+        inner.set_body_inverse(Box::new(|b| Ok(b.iter().map(|x| x + 1).collect())));
+        let log = MmapOffsetLog::from_offset_log(inner)?;
+        let data = log.get_cow(offsets[3])?;
+        assert!(matches!(data, Cow::Owned(_)));
+        assert_eq!(data, &[4; 3][..]);
+        Ok(())
+    }
+
+    #[test]
+    fn get_applies_body_inverse() -> Result<(), Error> {
+        let file = tempfile()?;
+        let with_transforms = |log: &mut OffsetLog<u32>| {
+            log.set_body_transform(Box::new(|b| Ok(b.to_ascii_uppercase())));
+            log.set_body_inverse(Box::new(|b| Ok(b.to_ascii_lowercase())));
+        };
+        let mut inner = OffsetLog::<u32>::from_file(file.try_clone()?)?;
+        with_transforms(&mut inner);
+        let mut log = MmapOffsetLog::from_offset_log(inner)?;
+        let a = log.append(b"Hello")?;
+
+        let mut plain = OffsetLog::<u32>::from_file(file)?;
+        with_transforms(&mut plain);
+        assert_eq!(log.get(a)?, b"hello");
+        assert_eq!(log.get(a)?, plain.get(a)?);
+        assert_eq!(log.get_slice(a)?, b"HELLO");
+        Ok(())
+    }
+
+    #[test]
+    fn many_random_gets() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut log = MmapOffsetLog::<u64>::from_file(file.try_clone()?)?;
+
+        let mut offsets = Vec::new();
+        for i in 0..1000u32 {
+            let size = (i % 97) as usize;
+            offsets.push((log.append(&vec![i as u8; size])?, i, size));
+        }
+
+        // Compare against a plain OffsetLog over the same file.
+        let plain = OffsetLog::<u64>::from_file(file)?;
+        let mut state = 0x2545_f491_u64;
+        for _ in 0..10_000 {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let (offset, i, size) = offsets[(state % offsets.len() as u64) as usize];
+
+            let data = log.get(offset)?;
+            assert_eq!(data, vec![i as u8; size]);
+            assert_eq!(data, plain.get(offset)?);
+        }
+        Ok(())
+    }
+}
@@ -1,17 +1,26 @@
-pub use bidir_iter::{BidirIterator, Forward};
+pub use bidir_iter::{Backward, BidirIterator, Forward};
 
+use crate::crc32::crc32;
 use crate::flume_log::*;
+use crate::gz_offset_log::GzOffsetLog;
 use crate::iter_at_offset::IterAtOffset;
-use crate::log_entry::LogEntry;
+use crate::json_lines_reader::JsonLinesReader;
+use crate::log_entry::{LogEntry, TaggedLogEntry};
 use buffered_offset_reader::{BufOffsetReader, OffsetRead, OffsetReadMut, OffsetWrite};
 use byteorder::{BigEndian, ReadBytesExt};
 use bytes::{BufMut, BytesMut};
+use ssb_crypto::hash::hash;
+use std::borrow::Cow;
+use std::convert::TryFrom;
 use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::{Seek, SeekFrom};
+use std::io::{IoSlice, Seek, SeekFrom, Write};
 use std::marker::PhantomData;
 use std::mem::size_of;
 use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Mutex, PoisonError};
 
 #[derive(Debug, Fail)]
 pub enum FlumeOffsetLogError {
@@ -20,13 +29,191 @@ pub enum FlumeOffsetLogError {
 
     #[fail(display = "The decode buffer passed to decode was too small")]
     DecodeBufferSizeTooSmall {},
+
+    #[fail(display = "Offset {} is not the start or end of a frame", offset)]
+    NotFrameBoundary { offset: u64 },
+
+    #[fail(display = "The log was not opened with a commit marker")]
+    NoCommitMarker {},
+
+    #[fail(display = "The entry at offset {} has no tag", offset)]
+    MissingTag { offset: u64 },
+
+    #[fail(
+        display = "The checksum of the entry at offset {} does not match",
+        offset
+    )]
+    ChecksumMismatch { offset: u64 },
+
+    #[fail(display = "Offset {} is not the start of an entry in the log", offset)]
+    InvalidOffset { offset: u64 },
+
+    #[fail(
+        display = "The entry at offset {} has unknown compression marker {}",
+        offset, marker
+    )]
+    UnknownCompression { offset: u64, marker: u8 },
+
+    #[fail(display = "The log is corrupt at offset {}", offset)]
+    CorruptAt { offset: u64 },
+
+    #[fail(display = "Offset {} is too large for the log's offset width", offset)]
+    OffsetTooLarge { offset: u64 },
+
+    #[fail(display = "Offsets {} bytes wide are not supported", width)]
+    UnsupportedOffsetWidth { width: usize },
+
+    #[fail(display = "The log was opened read-only")]
+    ReadOnly {},
+
+    #[fail(display = "Unable to detect the offset width of the log")]
+    UnknownOffsetWidth {},
+
+    #[fail(display = "Unable to open {}: {}", path, error)]
+    OpenFailed {
+        path: String,
+        #[fail(cause)]
+        error: io::Error,
+    },
+
+    #[fail(display = "Unable to get the length of the log file: {}", error)]
+    StatFailed {
+        #[fail(cause)]
+        error: io::Error,
+    },
+
+    #[fail(
+        display = "The commit marker is at {}, past the end of the log at {}",
+        committed, length
+    )]
+    CommitMarkerPastEnd { committed: u64, length: u64 },
+}
+
+mod sealed {
+    pub trait Sealed {}
 }
 
+/// The types an `OffsetLog` can use as its `ByteType`. The size of the type
+/// is the width of the offset stored at the end of each frame:
+///
+/// - `u32` and `u64` are the usual widths.
+/// - `u16` makes compact logs, which can't grow past 64 KiB.
+/// - `u128` stores offsets in 16 bytes, the top 8 of which are zero.
+/// - `()` leaves the offset out, so frames are just `[len, data, len]`.
+///
+/// This trait is sealed, so a log can't be opened with any other type.
+pub trait OffsetType: sealed::Sealed {}
+
+impl sealed::Sealed for () {}
+impl sealed::Sealed for u16 {}
+impl sealed::Sealed for u32 {}
+impl sealed::Sealed for u64 {}
+impl sealed::Sealed for u128 {}
+impl OffsetType for () {}
+impl OffsetType for u16 {}
+impl OffsetType for u32 {}
+impl OffsetType for u64 {}
+impl OffsetType for u128 {}
+
+/// The width of the trailing offset field in each frame, ie. the
+/// `ByteType` an `OffsetLog` should be opened with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OffsetWidth {
+    U32,
+    U64,
+}
+
+/// Detect whether an existing log was written as `OffsetLog<u32>` or
+/// `OffsetLog<u64>`, by checking which trailing offset in the first frame
+/// points to the end of that frame.
+pub fn detect_width<P: AsRef<Path>>(path: P) -> Result<OffsetWidth, Error> {
+    let file = File::open(path)?;
+
+    let mut head = [0; size_of::<u32>()];
+    if file.read_at(&mut head, 0)? < head.len() {
+        return Err(FlumeOffsetLogError::UnknownOffsetWidth {}.into());
+    }
+    let data_size = (&head[..]).read_u32::<BigEndian>()? as u64;
+
+    let mut tail = [0; size_of::<u32>() + size_of::<u64>()];
+    let tail_start = size_of::<u32>() as u64 + data_size;
+    let n = file.read_at(&mut tail, tail_start)?;
+
+    let width_matches = |width: usize| -> Result<bool, Error> {
+        if n < size_of::<u32>() + width {
+            return Ok(false);
+        }
+        let sz = (&tail[..]).read_u32::<BigEndian>()? as u64;
+        let next = (&tail[size_of::<u32>()..]).read_uint::<BigEndian>(width)?;
+        Ok(sz == data_size && next == tail_start + (size_of::<u32>() + width) as u64)
+    };
+
+    if width_matches(size_of::<u32>())? {
+        Ok(OffsetWidth::U32)
+    } else if width_matches(size_of::<u64>())? {
+        Ok(OffsetWidth::U64)
+    } else {
+        Err(FlumeOffsetLogError::UnknownOffsetWidth {}.into())
+    }
+}
+
+const COMMIT_MARKER_SUFFIX: &str = ".committed";
+const SUMMARY_SUFFIX: &str = ".summary";
+
+// Summary is [entry count: u64, last offset: u64, end of file: u64, crc32 of the rest: u32].
+// A last offset of u64::MAX means the log is empty.
+const SUMMARY_SIZE: usize = size_of::<u64>() * 3 + size_of::<u32>();
+
+// The number of data bytes `append_iter` gathers before writing them out.
+const APPEND_ITER_CHUNK_SIZE: usize = 64 * 1024;
+
+// Marks the entry count as not yet known.
+const UNKNOWN_COUNT: u64 = u64::MAX;
+
+/// When an `OffsetLog` makes appended entries durable on disk.
+///
+/// Unless a write buffer is set (see `set_write_buffer_capacity`), appends
+/// are written straight to the file with no buffering in the process, so
+/// an entry whose append returned `Ok` survives the program exiting
+/// whatever the setting. What's at stake is the machine crashing
+/// before the OS has written its cache out.
+///
+/// Syncing waits for the disk, so `EveryWrite` makes each append much
+/// slower (often by orders of magnitude on spinning disks) in exchange for
+/// never losing an entry whose append returned `Ok`. With `Manual` and
+/// `OnDrop`, entries appended since the last sync may be lost if the
+/// machine crashes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Durability {
+    /// Only sync when `flush` is called. This is the default.
+    Manual,
+    /// Sync the log file before every `append` or batch append returns.
+    /// The summary, if there is one, is still only written by `flush` and
+    /// on drop.
+    EveryWrite,
+    /// `flush` when the log is dropped, so the "append then exit" pattern
+    /// leaves the log synced and its summary up to date.
+    OnDrop,
+}
+
+/// A function applied to entry data as it's written to or read from a log.
+pub type BodyTransform = Box<dyn Fn(&[u8]) -> Result<Vec<u8>, Error> + Send + Sync>;
+
 pub struct OffsetLog<ByteType> {
     pub file: File,
     end_of_file: u64,
     last_offset: Option<u64>,
     tmp_buffer: BytesMut,
+    commit_marker: Option<File>,
+    summary: Option<File>,
+    entry_count: AtomicU64,
+    body_transform: Option<BodyTransform>,
+    body_inverse: Option<BodyTransform>,
+    subscribers: Mutex<Vec<Sender<u64>>>,
+    durability: Durability,
+    read_only: bool,
+    write_buffer: Mutex<Vec<u8>>,
+    write_buffer_capacity: usize,
     byte_type: PhantomData<ByteType>,
 }
 
@@ -50,24 +237,56 @@ pub struct ReadResult {
 }
 
 impl<ByteType> OffsetLog<ByteType> {
-    pub fn new<P: AsRef<Path>>(path: P) -> Result<OffsetLog<ByteType>, Error> {
-        let file = OpenOptions::new()
-            .read(true)
-            .write(true)
-            .create(true)
-            .open(&path)?;
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<OffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+
+        OffsetLog::from_file(open_file(&options, path)?)
+    }
+
+    /// Open a log without write access, for example on a read-only mount.
+    /// Reads work as usual; `append`, `append_batch`, `clear` and
+    /// `truncate_to` fail with `ReadOnly` without touching the file.
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<OffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        let file = open_file(OpenOptions::new().read(true), path)?;
+
+        let mut log = OffsetLog::from_file(file)?;
+        log.read_only = true;
+        Ok(log)
+    }
 
-        OffsetLog::from_file(file)
+    pub fn is_read_only(&self) -> bool {
+        self.read_only
     }
 
-    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<OffsetLog<ByteType>, Error> {
-        let file = OpenOptions::new().read(true).open(&path)?;
+    fn check_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            return Err(FlumeOffsetLogError::ReadOnly {}.into());
+        }
+        Ok(())
+    }
 
-        OffsetLog::from_file(file)
+    /// Open a gzipped log for sequential reading. See `GzOffsetLog`.
+    pub fn open_gz_read_only<P: AsRef<Path>>(path: P) -> Result<GzOffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        GzOffsetLog::open_read_only(path)
     }
 
-    pub fn from_file(mut file: File) -> Result<OffsetLog<ByteType>, Error> {
-        let file_length = file.seek(SeekFrom::End(0))?;
+    pub fn from_file(mut file: File) -> Result<OffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        let file_length = file
+            .seek(SeekFrom::End(0))
+            .map_err(|error| FlumeOffsetLogError::StatFailed { error })?;
 
         let last_offset = if file_length > 0 {
             let frame = read_prev_frame::<ByteType, _>(file_length, |b, o| file.read_at(b, o))?;
@@ -81,282 +300,1668 @@ impl<ByteType> OffsetLog<ByteType> {
             end_of_file: file_length,
             last_offset,
             tmp_buffer: BytesMut::new(),
+            commit_marker: None,
+            summary: None,
+            entry_count: AtomicU64::new(UNKNOWN_COUNT),
+            body_transform: None,
+            body_inverse: None,
+            subscribers: Mutex::new(Vec::new()),
+            durability: Durability::Manual,
+            read_only: false,
+            write_buffer: Mutex::new(Vec::new()),
+            write_buffer_capacity: 0,
             byte_type: PhantomData,
         })
     }
 
-    pub fn end(&self) -> u64 {
-        self.end_of_file
+    /// Open a log alongside a sidecar file (`<path>.committed`) that records
+    /// the committed length of the log. See `from_file_with_commit_marker`.
+    pub fn open_with_commit_marker<P: AsRef<Path>>(path: P) -> Result<OffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        let mut marker_path = path.as_ref().as_os_str().to_owned();
+        marker_path.push(COMMIT_MARKER_SUFFIX);
+
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+
+        let file = open_file(&options, path)?;
+        let marker = open_file(&options, marker_path)?;
+        OffsetLog::from_file_with_commit_marker(file, marker)
     }
 
-    pub fn read(&self, offset: u64) -> Result<ReadResult, Error> {
-        read_next::<ByteType, _>(offset, &self.file)
+    /// Open a log with a commit marker file, which holds the length of the
+    /// log as of the last commit.
+    ///
+    /// Anything past the committed length is the remains of an interrupted
+    /// `append_batch_atomic` and is truncated away before the log is opened.
+    /// A committed length past the end of the file means committed data has
+    /// gone missing, and is a `CommitMarkerPastEnd` error.
+    ///
+    /// The marker is only moved once the data it covers is synced to disk,
+    /// so every append to a log with a commit marker syncs the log file.
+    pub fn from_file_with_commit_marker(
+        file: File,
+        marker: File,
+    ) -> Result<OffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        let mut buf = [0; size_of::<u64>()];
+        if marker.read_at(&mut buf, 0)? == buf.len() {
+            let committed = (&buf[..]).read_u64::<BigEndian>()?;
+            let length = file.metadata()?.len();
+            if committed > length {
+                return Err(FlumeOffsetLogError::CommitMarkerPastEnd { committed, length }.into());
+            }
+            if committed < length {
+                file.set_len(committed)?;
+                file.sync_all()?;
+            }
+        }
+
+        let mut log = OffsetLog::from_file(file)?;
+        log.commit_marker = Some(marker);
+        log.write_commit_marker()?;
+        Ok(log)
     }
 
-    pub fn append_batch<T: AsRef<[u8]>>(&mut self, buffs: &[T]) -> Result<Vec<u64>, Error> {
-        let mut bytes = BytesMut::new();
-        let mut offsets = Vec::<u64>::new();
+    // Move the commit marker to the end of the log. Buffered appends are
+    // written out and the log is synced first, so the marker never covers
+    // data that isn't on disk.
+    fn write_commit_marker(&self) -> Result<(), Error> {
+        if let Some(marker) = &self.commit_marker {
+            self.flush_writes()?;
+            self.file.sync_data()?;
+            write_all_at(marker, &self.end_of_file.to_be_bytes(), 0)?;
+        }
+        Ok(())
+    }
 
-        let new_end = buffs.iter().try_fold(self.end_of_file, |offset, buff| {
-            //Maybe there's a more functional way of doing this. Kinda mixing functional and
-            //imperative.
-            offsets.push(offset);
-            encode::<ByteType>(offset, &buff.as_ref(), &mut bytes)
-        })?;
+    /// Open a log alongside a sidecar file (`<path>.summary`) that records
+    /// the number of entries in the log. See `from_file_with_summary`.
+    pub fn open_with_summary<P: AsRef<Path>>(path: P) -> Result<OffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        let mut summary_path = path.as_ref().as_os_str().to_owned();
+        summary_path.push(SUMMARY_SUFFIX);
+
+        let mut options = OpenOptions::new();
+        options.read(true).write(true).create(true).truncate(false);
+
+        let file = open_file(&options, path)?;
+        let summary = open_file(&options, summary_path)?;
+        OffsetLog::from_file_with_summary(file, summary)
+    }
 
-        offsets.last().map(|o| self.last_offset = Some(*o));
+    /// Open a log with a checksummed summary file, which holds the entry
+    /// count and last offset of the log as of the last `write_summary`.
+    ///
+    /// If the summary is intact and matches the length of the log, it's
+    /// trusted and `len()` is O(1). Otherwise the log is scanned and the
+    /// summary is rewritten.
+    pub fn from_file_with_summary(file: File, summary: File) -> Result<OffsetLog<ByteType>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        let mut log = OffsetLog::from_file(file)?;
+
+        let mut buf = [0; SUMMARY_SIZE];
+        let n = summary.read_at(&mut buf, 0)?;
+        let (body, checksum) = buf.split_at(SUMMARY_SIZE - size_of::<u32>());
+
+        let mut trusted = false;
+        if n == SUMMARY_SIZE && (&checksum[..]).read_u32::<BigEndian>()? == crc32(body) {
+            let count = (&body[..]).read_u64::<BigEndian>()?;
+            let last = (&body[8..]).read_u64::<BigEndian>()?;
+            let end = (&body[16..]).read_u64::<BigEndian>()?;
+
+            if end == log.end_of_file {
+                *log.entry_count.get_mut() = count;
+                log.last_offset = if last == u64::MAX { None } else { Some(last) };
+                trusted = true;
+            }
+        }
 
-        self.file.write_at(&bytes, self.end_of_file)?;
-        self.end_of_file = new_end;
+        if !trusted {
+            log.len();
+        }
+        log.summary = Some(summary);
+        if !trusted {
+            log.write_summary()?;
+        }
+        Ok(log)
+    }
 
-        Ok(offsets)
+    /// Write the log's entry count, last offset and length to its summary
+    /// file and sync it. Does nothing if the log has no summary.
+    pub fn write_summary(&self) -> Result<(), Error> {
+        if let (Some(summary), Some(count)) = (&self.summary, self.cached_len()) {
+            let mut buf = BytesMut::with_capacity(SUMMARY_SIZE);
+            buf.put_u64(count);
+            buf.put_u64(self.last_offset.unwrap_or(u64::MAX));
+            buf.put_u64(self.end_of_file);
+            let checksum = crc32(&buf);
+            buf.put_u32(checksum);
+
+            write_all_at(summary, &buf, 0)?;
+            summary.sync_data()?;
+        }
+        Ok(())
     }
 
-    pub fn iter(&self) -> Forward<OffsetLogIter<ByteType>> {
-        OffsetLogIter::new(self.file.try_clone().unwrap()).forward_owned()
+    /// Sync the log to disk, and update its summary if it has one.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        self.flush_writes()?;
+        self.file.sync_data()?;
+        self.write_summary()
     }
 
-    pub fn bidir_iter(&self) -> OffsetLogIter<ByteType> {
-        // TODO: what are the chances that try_clone() will fail?
-        //  I'd rather not return a Result<> here.
-        OffsetLogIter::new(self.file.try_clone().unwrap())
+    /// Set when appends are synced to disk. See `Durability`.
+    pub fn set_durability(&mut self, durability: Durability) {
+        self.durability = durability;
     }
 
-    pub fn bidir_iter_at_offset(&self, offset: u64) -> OffsetLogIter<ByteType> {
-        OffsetLogIter::with_starting_offset(self.file.try_clone().unwrap(), offset)
+    /// Buffer appends in memory, up to `capacity` bytes, and write them to
+    /// the file together. This saves a syscall per entry when appending
+    /// many small entries. A capacity of 0, the default, writes each append
+    /// as it's made. Buffered entries are written out before the log is
+    /// read, by `flush`, and when the log is dropped.
+    ///
+    /// An error writing buffered entries is returned from the call that
+    /// triggered the write, and the entries stay buffered to be tried
+    /// again. Iterators can't return the error, so they only see the
+    /// entries that made it to the file.
+    ///
+    /// `Durability::EveryWrite` needs each append on disk before it
+    /// returns, so it bypasses the buffer.
+    pub fn set_write_buffer_capacity(&mut self, capacity: usize) -> Result<(), Error> {
+        self.flush_writes()?;
+        self.write_buffer_capacity = capacity;
+        Ok(())
     }
-}
 
-impl<ByteType> FlumeLog for OffsetLog<ByteType> {
-    fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
-        self.read(seq_num).map(|r| r.entry.data)
+    // Write out any buffered appends.
+    pub(crate) fn flush_writes(&self) -> io::Result<()> {
+        let mut pending = self
+            .write_buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !pending.is_empty() {
+            let start = self.end_of_file - pending.len() as u64;
+            write_all_at(&self.file, &pending, start)?;
+            pending.clear();
+        }
+        Ok(())
     }
 
-    fn latest(&self) -> Option<u64> {
-        self.last_offset
+    // A clone of the file for an iterator to read through, with buffered
+    // appends written out first.
+    fn clone_file(&self) -> File {
+        let _ = self.flush_writes();
+        // TODO: what are the chances that try_clone() will fail?
+        //  I'd rather not return a Result<> here.
+        self.file.try_clone().unwrap()
     }
 
-    fn append(&mut self, buff: &[u8]) -> Result<u64, Error> {
-        self.tmp_buffer.clear();
-        self.tmp_buffer
-            .reserve(buff.len() + size_of_framing_bytes::<ByteType>());
+    // Buffer the encoded frames in `slices`, which start at the end of the
+    // log, or write them to the file if they don't fit in the buffer.
+    fn write_appended(&mut self, slices: &mut [IoSlice]) -> io::Result<()> {
+        let size: usize = slices.iter().map(|s| s.len()).sum();
+        let capacity = match self.durability {
+            Durability::EveryWrite => 0,
+            Durability::Manual | Durability::OnDrop => self.write_buffer_capacity,
+        };
 
-        let offset = self.end_of_file;
-        let new_end = encode::<ByteType>(offset, buff, &mut self.tmp_buffer)?;
-        self.file.write_at(&self.tmp_buffer, offset)?;
+        let pending_len = self
+            .write_buffer
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len();
+        if pending_len + size > capacity {
+            self.flush_writes()?;
+        }
+        if size <= capacity {
+            let pending = self
+                .write_buffer
+                .get_mut()
+                .unwrap_or_else(PoisonError::into_inner);
+            for slice in slices.iter() {
+                pending.extend_from_slice(slice);
+            }
+            return Ok(());
+        }
 
-        self.end_of_file = new_end;
-        self.last_offset = Some(offset);
-        Ok(offset)
+        self.file.seek(SeekFrom::Start(self.end_of_file))?;
+        write_all_vectored(&mut self.file, slices).and_then(|()| self.sync_appended())
     }
 
-    fn clear(&mut self, _seq_num: u64) {
-        unimplemented!();
+    // Called after each append is written, before it counts as done.
+    fn sync_appended(&self) -> io::Result<()> {
+        match self.durability {
+            Durability::EveryWrite => self.file.sync_data(),
+            Durability::Manual | Durability::OnDrop => Ok(()),
+        }
     }
-}
-
-pub struct OffsetLogIter<ByteType> {
-    reader: BufOffsetReader<File>,
-    current: u64,
-    next: u64,
-    byte_type: PhantomData<ByteType>,
-}
 
-impl<ByteType> OffsetLogIter<ByteType> {
-    pub fn new(file: File) -> OffsetLogIter<ByteType> {
-        OffsetLogIter::with_starting_offset(file, 0)
+    /// The number of entries in the log. The first call scans the whole
+    /// log, unless it was opened with a summary, and the count is kept up
+    /// to date by appends after that.
+    pub fn len(&self) -> u64 {
+        if let Some(count) = self.cached_len() {
+            return count;
+        }
+        let count = self.iter().count() as u64;
+        self.entry_count.store(count, Ordering::Relaxed);
+        count
     }
 
-    pub fn with_starting_offset(file: File, offset: u64) -> OffsetLogIter<ByteType> {
-        OffsetLogIter {
-            reader: BufOffsetReader::new(file),
-            current: offset,
-            next: offset,
-            byte_type: PhantomData,
+    fn cached_len(&self) -> Option<u64> {
+        match self.entry_count.load(Ordering::Relaxed) {
+            UNKNOWN_COUNT => None,
+            count => Some(count),
         }
     }
-}
 
-impl<ByteType> IterAtOffset<Forward<OffsetLogIter<ByteType>>> for OffsetLog<ByteType> {
-    fn iter_at_offset(&self, offset: u64) -> Forward<OffsetLogIter<ByteType>> {
-        OffsetLogIter::with_starting_offset(self.file.try_clone().unwrap(), offset).forward_owned()
+    fn add_to_len(&mut self, n: u64) {
+        let count = self.entry_count.get_mut();
+        if *count != UNKNOWN_COUNT {
+            *count += n;
+        }
     }
-}
 
-impl<ByteType> BidirIterator for OffsetLogIter<ByteType> {
-    type Item = LogEntry;
+    pub fn is_empty(&self) -> bool {
+        self.end_of_file == 0
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.current = self.next;
-        let r = read_next_mut::<u32, _>(self.current, &mut self.reader).ok()?;
-        self.next = r.next;
-        Some(r.entry)
+    /// Set a transform that's applied to every entry by `append` and
+    /// `append_batch` before it's framed and written.
+    pub fn set_body_transform(&mut self, transform: BodyTransform) {
+        self.body_transform = Some(transform);
     }
 
-    fn prev(&mut self) -> Option<Self::Item> {
-        self.next = self.current;
-        let r = read_prev_mut::<u32, _>(self.current, &mut self.reader).ok()?;
-        self.current = r.entry.offset;
-        Some(r.entry)
+    /// Set a transform that's applied to entry data returned by `get`,
+    /// typically undoing the body transform. `read` and the iterators always
+    /// return the data as it's stored.
+    pub fn set_body_inverse(&mut self, inverse: BodyTransform) {
+        self.body_inverse = Some(inverse);
     }
-}
 
-fn size_of_frame_tail<T>() -> usize {
-    size_of::<u32>() + size_of::<T>()
-}
-fn size_of_framing_bytes<T>() -> usize {
-    size_of::<u32>() * 2 + size_of::<T>()
-}
+    /// The offset just past the last entry, which is where the next entry
+    /// will be appended. Unlike `latest`, this is a byte position rather
+    /// than the offset of an entry.
+    pub fn end(&self) -> u64 {
+        self.end_of_file
+    }
 
-pub fn encode<T>(offset: u64, item: &[u8], dest: &mut BytesMut) -> Result<u64, Error> {
-    let chunk_size = size_of_framing_bytes::<T>() + item.len();
-    dest.reserve(chunk_size);
-    dest.put_u32(item.len() as u32);
-    dest.put_slice(&item);
-    dest.put_u32(item.len() as u32);
-    let next_offset = offset + chunk_size as u64;
+    /// Get a channel that receives the offset of every entry appended to
+    /// the log from now on, in order. Use `get` to read the new entries.
+    ///
+    /// The channel is unbounded, so a subscriber that falls behind doesn't
+    /// slow down appends; offsets queue up until it catches up. Dropping the
+    /// receiver unsubscribes. A log with no subscribers does no extra work
+    /// when appending.
+    pub fn subscribe(&self) -> Receiver<u64> {
+        let (sender, receiver) = channel();
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(sender);
+        receiver
+    }
 
-    dest.put_uint(next_offset, size_of::<T>());
-    Ok(next_offset)
-}
+    // Send newly appended offsets to subscribers, dropping any that have
+    // gone away.
+    fn publish(&mut self, offsets: &[u64]) {
+        let subscribers = self
+            .subscribers
+            .get_mut()
+            .unwrap_or_else(PoisonError::into_inner);
+        subscribers.retain(|s| offsets.iter().all(|o| s.send(*o).is_ok()));
+    }
 
-pub fn validate_entry<T>(offset: u64, data_size: usize, rest: &[u8]) -> Result<u64, Error> {
-    if rest.len() != data_size + size_of_frame_tail::<T>() {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    /// Read the entry at `offset`. An offset that isn't the start of a
+    /// whole frame in the log is an `InvalidOffset` error. The frame's size
+    /// is checked against the end of the log before anything is allocated
+    /// for it, so a bad offset can't cause a huge allocation.
+    pub fn read(&self, offset: u64) -> Result<ReadResult, Error> {
+        self.flush_writes()?;
+        self.read_with(offset, &mut |b, o| self.file.read_at(b, o))
     }
 
-    let sz = (&rest[data_size..]).read_u32::<BigEndian>()? as usize;
-    if sz != data_size {
-        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+    /// Get the data of several entries, in the order of `seqs`. The entries
+    /// are read in file order through a buffer, so entries close together
+    /// in the log are read together.
+    ///
+    /// If any entry can't be read the whole batch fails, with the same
+    /// error `get` would give for that entry.
+    pub fn get_batch(&self, seqs: &[u64]) -> Result<Vec<Vec<u8>>, Error> {
+        let mut order: Vec<usize> = (0..seqs.len()).collect();
+        order.sort_by_key(|&i| seqs[i]);
+
+        self.flush_writes()?;
+        let mut reader = BufOffsetReader::new(self.file.try_clone()?);
+        let mut results = vec![Vec::new(); seqs.len()];
+        for i in order {
+            let r = self.read_with(seqs[i], &mut |b, o| reader.read_at(b, o))?;
+            results[i] = self.inverse_body(r.entry.data)?;
+        }
+        Ok(results)
     }
 
-    let next =
-        (&rest[(data_size + size_of::<u32>())..]).read_uint::<BigEndian>(size_of::<T>())? as u64;
+    fn read_with<F>(&self, offset: u64, read_at: &mut F) -> Result<ReadResult, Error>
+    where
+        F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+    {
+        let invalid = || -> Error { FlumeOffsetLogError::InvalidOffset { offset }.into() };
+        if offset >= self.end_of_file {
+            return Err(invalid());
+        }
 
-    // `next` should be equal to the offset of the next entry
-    // which may or may not be immediately following this one (I suppose)
-    if next < offset + size_of::<u32>() as u64 + rest.len() as u64 {
-        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+        // IO errors are passed on; anything wrong with the frame itself
+        // means `offset` didn't point at one.
+        let check = |e: Error| match e.downcast_ref::<FlumeOffsetLogError>() {
+            Some(_) => invalid(),
+            None => e,
+        };
+
+        let frame = read_next_frame(offset, read_at).map_err(check)?;
+        let frame_size = (size_of_framing_bytes::<ByteType>() + frame.data_size) as u64;
+        if frame_size > self.end_of_file - offset {
+            return Err(invalid());
+        }
+        read_entry::<ByteType, _>(&frame, read_at).map_err(check)
     }
-    Ok(next)
-}
 
-pub fn read_next<ByteType, R: OffsetRead>(offset: u64, r: &R) -> Result<ReadResult, Error> {
-    read_next_impl::<ByteType, _>(offset, |b, o| r.read_at(b, o))
-}
+    /// Like `get`, but returns a `Cow` so that backends which can hand out
+    /// borrowed data don't have to copy it. Reading from a file always
+    /// produces owned data; `MmapOffsetLog::get_cow` borrows from its map.
+    pub fn get_cow(&self, seq_num: u64) -> Result<Cow<'_, [u8]>, Error> {
+        self.get(seq_num).map(Cow::Owned)
+    }
 
-pub fn read_next_mut<ByteType, R: OffsetReadMut>(
-    offset: u64,
-    r: &mut R,
-) -> Result<ReadResult, Error> {
-    read_next_impl::<ByteType, _>(offset, |b, o| r.read_at(b, o))
-}
+    /// A SHA-256 digest of every entry in the log, in order. Two logs have
+    /// the same content hash iff they hold the same sequence of entries.
+    pub fn content_hash(&self) -> Result<[u8; 32], Error> {
+        self.content_hash_up_to(self.last_offset.unwrap_or(0))
+    }
 
-pub fn read_prev<ByteType, R: OffsetRead>(offset: u64, r: &R) -> Result<ReadResult, Error> {
-    read_prev_impl::<ByteType, _>(offset, |b, o| r.read_at(b, o))
-}
+    /// Like `content_hash`, but only covers entries up to and including the
+    /// one at `seq_num`.
+    pub fn content_hash_up_to(&self, seq_num: u64) -> Result<[u8; 32], Error> {
+        // Chain the digests: h' = sha256(h, len(data), data)
+        let mut digest = [0; 32];
+        let mut buf = BytesMut::new();
+        let mut offset = 0;
+
+        while offset < self.end_of_file && offset <= seq_num {
+            let r = self.read(offset)?;
+
+            buf.clear();
+            buf.reserve(digest.len() + size_of::<u64>() + r.entry.data.len());
+            buf.put_slice(&digest);
+            buf.put_u64(r.entry.data.len() as u64);
+            buf.put_slice(&r.entry.data);
+            digest = hash(&buf).0;
+
+            offset = r.next;
+        }
+        Ok(digest)
+    }
 
-pub fn read_prev_mut<ByteType, R: OffsetReadMut>(
-    offset: u64,
-    r: &mut R,
-) -> Result<ReadResult, Error> {
-    read_prev_impl::<ByteType, _>(offset, |b, o| r.read_at(b, o))
-}
+    /// Find the first entry whose data matches `pred`, returning its offset
+    /// and data. Entries are checked in place, and only the match is copied.
+    pub fn find_first<F>(&self, pred: F) -> Result<Option<(u64, Vec<u8>)>, Error>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        self.flush_writes()?;
+        let mut reader = BufOffsetReader::new(self.file.try_clone()?);
+        let mut buf = Vec::new();
+        let mut offset = 0;
+
+        while offset < self.end_of_file {
+            let mut read_at = |b: &mut [u8], o| reader.read_at(b, o);
+            let frame = read_next_frame(offset, &mut read_at)?;
+            let next = read_frame_into::<ByteType, _>(&frame, &mut read_at, &mut buf)?;
+
+            if pred(&buf[..frame.data_size]) {
+                buf.truncate(frame.data_size);
+                return Ok(Some((offset, buf)));
+            }
+            offset = next;
+        }
+        Ok(None)
+    }
 
-fn read_next_impl<ByteType, F>(offset: u64, mut read_at: F) -> Result<ReadResult, Error>
-where
-    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
-{
-    let frame = read_next_frame::<ByteType, _>(offset, &mut read_at)?;
-    read_entry::<ByteType, _>(&frame, &mut read_at)
-}
+    /// Append every entry whose data matches `pred` to `dest`, returning
+    /// the number of entries copied. The source log is left unchanged.
+    ///
+    /// `pred` and `dest` see each entry as `get` would return it, with the
+    /// body inverse applied. A frame that can't be read stops the copy with
+    /// a `CorruptAt` error, rather than leaving `dest` silently short.
+    pub fn copy_filtered<B, F>(&self, dest: &mut OffsetLog<B>, pred: F) -> Result<u64, Error>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        self.flush_writes()?;
+        let mut reader = BufOffsetReader::new(self.file.try_clone()?);
+        let mut buf = Vec::new();
+        let mut copied = 0;
+        let mut offset = 0;
+
+        while offset < self.end_of_file {
+            let mut read_at = |b: &mut [u8], o| reader.read_at(b, o);
+            let corrupt = |e: Error| match e.downcast::<FlumeOffsetLogError>() {
+                Ok(_) => FlumeOffsetLogError::CorruptAt { offset }.into(),
+                Err(e) => e,
+            };
+            let frame = read_next_frame(offset, &mut read_at).map_err(corrupt)?;
+            let next =
+                read_frame_into::<ByteType, _>(&frame, &mut read_at, &mut buf).map_err(corrupt)?;
+
+            let data = &buf[..frame.data_size];
+            let data = if self.has_body_inverse() {
+                Cow::Owned(self.inverse_body(data.to_vec())?)
+            } else {
+                Cow::Borrowed(data)
+            };
+            if pred(&data) {
+                dest.append(&data)?;
+                copied += 1;
+            }
+            offset = next;
+        }
+        Ok(copied)
+    }
 
-fn read_prev_impl<ByteType, F>(offset: u64, mut read_at: F) -> Result<ReadResult, Error>
-where
-    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
-{
-    let frame = read_prev_frame::<ByteType, _>(offset, &mut read_at)?;
-    read_entry::<ByteType, _>(&frame, &mut read_at)
-}
+    /// Read the last `n` entries of the log, walking backward from the end
+    /// of the file. The entries are returned oldest first, as
+    /// `(offset, data)` pairs.
+    pub fn tail(&self, n: usize) -> Result<Vec<(u64, Vec<u8>)>, Error> {
+        self.flush_writes()?;
+        let mut entries = Vec::new();
+        let mut offset = self.end_of_file;
+
+        while entries.len() < n && offset > 0 {
+            let r = read_prev::<ByteType, _>(offset, &self.file)?;
+            offset = r.entry.offset;
+            entries.push((r.entry.offset, r.entry.data));
+        }
 
-fn read_next_frame<ByteType, F>(offset: u64, read_at: &mut F) -> Result<Frame, Error>
-where
-    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
-{
-    // Entry is [payload size: u32, payload, payload_size: u32, next_offset: ByteType]
+        entries.reverse();
+        Ok(entries)
+    }
 
-    const HEAD_SIZE: usize = size_of::<u32>();
+    /// Walk the log by its length prefixes and rewrite any trailing offset
+    /// field that doesn't point at the end of its frame. Returns the number
+    /// of frames that were fixed.
+    ///
+    /// The data and both length fields of every frame must be intact.
+    pub fn rewrite_offsets(&mut self) -> Result<u64, Error> {
+        self.check_writable()?;
+        self.flush_writes()?;
+        let tail_size = size_of_frame_tail::<ByteType>();
+        let mut tail = vec![0; tail_size];
+        let mut correct = BytesMut::with_capacity(size_of::<ByteType>());
+        let mut fixed = 0;
+        let mut offset = 0;
+
+        while offset < self.end_of_file {
+            let frame = read_next_frame(offset, &mut |b, o| self.file.read_at(b, o))?;
+            let tail_start = frame.data_start() + frame.data_size as u64;
+
+            let n = self.file.read_at(&mut tail, tail_start)?;
+            if n < tail_size {
+                return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+            }
+            let sz = (&tail[..]).read_u32::<BigEndian>()? as usize;
+            if sz != frame.data_size {
+                return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+            }
+
+            let next = tail_start + tail_size as u64;
+            let stored = read_trailing_offset::<ByteType>(&tail[size_of::<u32>()..], next)?;
+            if stored != next {
+                correct.clear();
+                put_offset::<ByteType>(next, &mut correct);
+                write_all_at(&self.file, &correct, tail_start + size_of::<u32>() as u64)?;
+                fixed += 1;
+            }
+            offset = next;
+        }
+        Ok(fixed)
+    }
 
-    let mut head_bytes = [0; HEAD_SIZE];
-    let n = read_at(&mut head_bytes, offset)?;
-    if n < HEAD_SIZE {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    /// Open the byte range `[start, end)` of this log as a read-only
+    /// `SubLog`. Both ends must fall on frame boundaries.
+    pub fn sub_log(&self, start: u64, end: u64) -> Result<SubLog<ByteType>, Error> {
+        self.flush_writes()?;
+        if !self.is_frame_boundary(start) {
+            return Err(FlumeOffsetLogError::NotFrameBoundary { offset: start }.into());
+        }
+        if end < start || !self.is_frame_boundary(end) {
+            return Err(FlumeOffsetLogError::NotFrameBoundary { offset: end }.into());
+        }
+
+        Ok(SubLog {
+            file: self.file.try_clone()?,
+            start,
+            end,
+            byte_type: PhantomData,
+        })
     }
 
-    let data_size = (&head_bytes[..]).read_u32::<BigEndian>()? as usize;
-    Ok(Frame { offset, data_size })
-}
+    fn is_frame_boundary(&self, offset: u64) -> bool {
+        offset == 0
+            || offset == self.end_of_file
+            || (offset < self.end_of_file
+                && self.read(offset).is_ok()
+                && read_prev::<ByteType, _>(offset, &self.file).is_ok())
+    }
 
-fn read_prev_frame<ByteType, F>(offset: u64, mut read_at: F) -> Result<Frame, Error>
-where
-    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
-{
-    let tail_size = size_of_frame_tail::<ByteType>(); // TODO: why can't this be const?
+    /// Append a batch of entries with a single vectored write.
+    ///
+    /// Only the framing bytes are buffered; the entry bodies are written
+    /// straight from the caller's slices, so peak memory stays close to the
+    /// size of the batch itself.
+    pub fn append_batch<T: AsRef<[u8]>>(&mut self, buffs: &[T]) -> Result<Vec<u64>, Error> {
+        let offsets = self.write_batch(buffs)?;
+        self.write_commit_marker()?;
+        Ok(offsets)
+    }
 
-    // big enough, assuming ByteType isn't bigger than a u64
-    let mut tmp = [0; size_of::<u32>() + size_of::<u64>()];
-    if tmp.len() as u64 > offset {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    /// Append every entry from `items`, returning their offsets in order.
+    ///
+    /// Entries are gathered into chunks of about 64 KiB and each chunk is
+    /// written like an `append_batch`, so memory use stays bounded however
+    /// long the iterator is. If a write fails, the chunks before it stay in
+    /// the log.
+    pub fn append_iter<I>(&mut self, items: I) -> Result<Vec<u64>, Error>
+    where
+        I: IntoIterator,
+        I::Item: AsRef<[u8]>,
+    {
+        let mut offsets = Vec::new();
+        let mut chunk = Vec::new();
+        let mut chunk_size = 0;
+        for item in items {
+            // Count the framing too, so that a run of tiny or empty entries
+            // is still split into chunks.
+            chunk_size += size_of_framing_bytes::<ByteType>() + item.as_ref().len();
+            chunk.push(item);
+            if chunk_size >= APPEND_ITER_CHUNK_SIZE {
+                offsets.extend(self.append_batch(&chunk)?);
+                chunk.clear();
+                chunk_size = 0;
+            }
+        }
+        if !chunk.is_empty() {
+            offsets.extend(self.append_batch(&chunk)?);
+        }
+        Ok(offsets)
     }
 
-    let n = read_at(&mut tmp[..tail_size], offset - tail_size as u64)?;
-    if n < tail_size {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    /// Append a batch of entries so that, after a crash, either the whole
+    /// batch or none of it is in the log.
+    ///
+    /// The batch is written and synced to disk before the commit marker is
+    /// moved past it, so the log must have been opened with a commit marker
+    /// (see `open_with_commit_marker`).
+    pub fn append_batch_atomic<T: AsRef<[u8]>>(&mut self, buffs: &[T]) -> Result<Vec<u64>, Error> {
+        if self.commit_marker.is_none() {
+            return Err(FlumeOffsetLogError::NoCommitMarker {}.into());
+        }
+
+        let offsets = self.write_batch(buffs)?;
+        self.write_commit_marker()?;
+        if let Some(marker) = &self.commit_marker {
+            marker.sync_data()?;
+        }
+        Ok(offsets)
     }
 
-    let data_size = (&tmp[..]).read_u32::<BigEndian>()? as usize;
-    if (data_size as u64) > offset {
-        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+    fn write_batch<T: AsRef<[u8]>>(&mut self, buffs: &[T]) -> Result<Vec<u64>, Error> {
+        if let Some(transform) = &self.body_transform {
+            let transformed = buffs
+                .iter()
+                .map(|b| transform(b.as_ref()))
+                .collect::<Result<Vec<_>, Error>>()?;
+            return self.write_frames(&transformed);
+        }
+        self.write_frames(buffs)
     }
 
-    let data_start = offset - tail_size as u64 - data_size as u64;
+    fn write_frames<T: AsRef<[u8]>>(&mut self, buffs: &[T]) -> Result<Vec<u64>, Error> {
+        self.check_writable()?;
+        let head_size = size_of::<u32>();
+        let tail_size = size_of_frame_tail::<ByteType>();
 
-    Ok(Frame {
-        offset: data_start - size_of::<u32>() as u64,
-        data_size,
-    })
-}
+        // Borrow the scratch buffer for the framing bytes; it's put back once
+        // the write is done.
+        let mut framing = std::mem::take(&mut self.tmp_buffer);
+        framing.clear();
+        framing.reserve(buffs.len() * (head_size + tail_size));
+        let mut offsets = Vec::<u64>::with_capacity(buffs.len());
 
-fn read_entry<ByteType, F>(frame: &Frame, read_at: &mut F) -> Result<ReadResult, Error>
-where
-    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
-{
-    // Entry is [payload size: u32, payload, payload_size: u32, next_offset: ByteType]
-    let tail_size = size_of_frame_tail::<ByteType>();
-    let to_read = frame.data_size + tail_size;
+        let new_end = buffs.iter().fold(self.end_of_file, |offset, buff| {
+            offsets.push(offset);
+            encode_framing::<ByteType>(offset, buff.as_ref().len(), &mut framing)
+        });
+        // Offsets only grow, so if the last one fits they all do.
+        if let Err(e) = check_offset_fits::<ByteType>(new_end) {
+            self.tmp_buffer = framing;
+            return Err(e);
+        }
 
-    let mut buf = Vec::with_capacity(to_read);
-    unsafe { buf.set_len(to_read) };
+        let mut slices = Vec::with_capacity(buffs.len() * 3);
+        for (i, buff) in buffs.iter().enumerate() {
+            let frame_start = i * (head_size + tail_size);
+            let (head, tail) =
+                framing[frame_start..frame_start + head_size + tail_size].split_at(head_size);
+            slices.push(IoSlice::new(head));
+            slices.push(IoSlice::new(buff.as_ref()));
+            slices.push(IoSlice::new(tail));
+        }
 
-    let n = read_at(&mut buf, frame.data_start())?;
-    if n < to_read {
-        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
-    }
+        let written = self.write_appended(&mut slices);
+        drop(slices);
+        self.tmp_buffer = framing;
+        if let Err(e) = written {
+            self.discard_partial_write();
+            return Err(e.into());
+        }
 
-    let next = validate_entry::<ByteType>(frame.offset, frame.data_size, &buf)?;
+        if let Some(o) = offsets.last() {
+            self.last_offset = Some(*o);
+        }
+        self.add_to_len(offsets.len() as u64);
+        self.end_of_file = new_end;
+        self.publish(&offsets);
 
-    // Chop the tail off of buf, so it only contains the entry data.
-    buf.truncate(frame.data_size);
+        Ok(offsets)
+    }
 
-    Ok(ReadResult {
+    // Encode a single entry into the scratch buffer with `encode_entry` and
+    // append it to the log. `size` is the expected size of the entry data.
+    fn write_entry<F>(&mut self, size: usize, encode_entry: F) -> Result<u64, Error>
+    where
+        F: FnOnce(u64, &mut BytesMut) -> Result<u64, Error>,
+    {
+        self.check_writable()?;
+        self.tmp_buffer.clear();
+        self.tmp_buffer
+            .reserve(size + size_of_framing_bytes::<ByteType>());
+
+        let offset = self.end_of_file;
+        let new_end = encode_entry(offset, &mut self.tmp_buffer)?;
+        let entry = std::mem::take(&mut self.tmp_buffer);
+        let written = self.write_appended(&mut [IoSlice::new(&entry)]);
+        self.tmp_buffer = entry;
+        if let Err(e) = written {
+            self.discard_partial_write();
+            return Err(e.into());
+        }
+
+        self.end_of_file = new_end;
+        self.last_offset = Some(offset);
+        self.add_to_len(1);
+        self.publish(&[offset]);
+        self.write_commit_marker()?;
+        Ok(offset)
+    }
+
+    pub(crate) fn has_body_inverse(&self) -> bool {
+        self.body_inverse.is_some()
+    }
+
+    // Apply the body inverse, if there is one, to data read from the log.
+    pub(crate) fn inverse_body(&self, data: Vec<u8>) -> Result<Vec<u8>, Error> {
+        match &self.body_inverse {
+            Some(inverse) => inverse(&data),
+            None => Ok(data),
+        }
+    }
+
+    // A failed write can leave part of a frame past the end of the log.
+    // Cut it off, so the next append starts from a clean end and the log
+    // can still be opened. This is best effort; the write error is the one
+    // that gets reported. Buffered appends aren't in the file yet, so the
+    // end of the file is short of `end_of_file` by the size of the buffer.
+    fn discard_partial_write(&self) {
+        let pending = self
+            .write_buffer
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .len();
+        let _ = self.file.set_len(self.end_of_file - pending as u64);
+    }
+
+    fn transform_body<'a>(&self, buff: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        match &self.body_transform {
+            Some(transform) => transform(buff).map(Cow::Owned),
+            None => Ok(Cow::Borrowed(buff)),
+        }
+    }
+
+    // The data `get` returns for `buff` once it's appended: the body
+    // transform followed by its inverse, if either is set.
+    pub(crate) fn read_back<'a>(&self, buff: &'a [u8]) -> Result<Cow<'a, [u8]>, Error> {
+        let stored = self.transform_body(buff)?;
+        match &self.body_inverse {
+            Some(inverse) => inverse(&stored).map(Cow::Owned),
+            None => Ok(stored),
+        }
+    }
+
+    /// Append an entry with a one byte tag, stored as the first byte of the
+    /// entry data. A log should be written with either only `append_tagged`
+    /// or none of it; see `TaggedLogEntry`.
+    pub fn append_tagged(&mut self, tag: u8, buff: &[u8]) -> Result<u64, Error> {
+        let buff = self.transform_body(buff)?;
+
+        self.write_entry(buff.len() + 1, |offset, dest| {
+            encode_tagged::<ByteType>(offset, tag, &buff, dest)
+        })
+    }
+
+    /// Read an entry written with `append_tagged`. Only valid on a log
+    /// written entirely with `append_tagged`.
+    pub fn read_tagged(&self, offset: u64) -> Result<TaggedLogEntry, Error> {
+        self.read(offset)?
+            .entry
+            .into_tagged()
+            .ok_or_else(|| FlumeOffsetLogError::MissingTag { offset }.into())
+    }
+
+    /// Iterate over the entries of a tagged log that have the given tag.
+    /// Only valid on a log written entirely with `append_tagged`.
+    pub fn iter_tagged(&self, tag: u8) -> impl Iterator<Item = TaggedLogEntry> {
+        self.iter()
+            .filter_map(LogEntry::into_tagged)
+            .filter(move |e| e.tag == tag)
+    }
+
+    /// Iterate over the log, stepping over corrupt regions instead of
+    /// stopping at them. Each corrupt region is reported in place, as the
+    /// range of bytes that was skipped to reach the next readable frame.
+    ///
+    /// This reads up to the end of the file, so it also reports a torn
+    /// write at the end of the log.
+    pub fn iter_recovering(&self) -> Result<RecoveringIter<ByteType>, Error> {
+        self.flush_writes()?;
+        Ok(RecoveringIter {
+            reader: BufOffsetReader::new(self.file.try_clone()?),
+            file: self.file.try_clone()?,
+            next: 0,
+            end: self.file.metadata()?.len(),
+            buf: Vec::new(),
+            found: None,
+            byte_type: PhantomData,
+        })
+    }
+
+    /// Check every frame in the log file, from the start to the end of the
+    /// file, returning the number of entries. Each frame's lengths must
+    /// match and its trailing offset must point at the frame after it.
+    ///
+    /// The first frame that fails is reported as `CorruptAt`, with its
+    /// offset. That includes a frame cut short by the end of the file.
+    pub fn validate(&self) -> Result<u64, Error> {
+        let mut frames = self.iter_recovering()?;
+        let mut count = 0;
+        while frames.next < frames.end {
+            let offset = frames.next;
+            match frames.read_frame(offset) {
+                Ok((_, next)) => frames.next = next,
+                Err(e) => match e.downcast::<FlumeOffsetLogError>() {
+                    Ok(_) => return Err(FlumeOffsetLogError::CorruptAt { offset }.into()),
+                    Err(e) => return Err(e),
+                },
+            }
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    /// Cut the log back to `offset`, discarding every entry from there on.
+    /// This is the way to recover from corruption found by `validate`.
+    ///
+    /// `offset` must be 0 or the end of an intact frame; the frames after
+    /// it don't have to be readable. Anything else is `NotFrameBoundary`.
+    pub fn truncate_to(&mut self, offset: u64) -> Result<(), Error> {
+        self.check_writable()?;
+        self.flush_writes()?;
+        let last_offset = if offset == 0 {
+            None
+        } else {
+            match read_prev::<ByteType, _>(offset, &self.file) {
+                Ok(r) => Some(r.entry.offset),
+                Err(e) => match e.downcast::<FlumeOffsetLogError>() {
+                    Ok(_) => return Err(FlumeOffsetLogError::NotFrameBoundary { offset }.into()),
+                    Err(e) => return Err(e),
+                },
+            }
+        };
+
+        self.file.set_len(offset)?;
+        self.end_of_file = offset;
+        self.last_offset = last_offset;
+        *self.entry_count.get_mut() = UNKNOWN_COUNT;
+        self.write_commit_marker()?;
+        if self.summary.is_some() {
+            self.len();
+            self.write_summary()?;
+        }
+        Ok(())
+    }
+
+    /// Iterate over the entries whose data passes `prefilter`.
+    ///
+    /// Each entry is checked in a reused buffer before it's copied out, so
+    /// entries that fail the prefilter cost no allocation.
+    pub fn iter_filtered<F>(&self, prefilter: F) -> OffsetLogFilteredIter<ByteType, F>
+    where
+        F: Fn(&[u8]) -> bool,
+    {
+        OffsetLogFilteredIter {
+            reader: BufOffsetReader::new(self.clone_file()),
+            next: 0,
+            buf: Vec::new(),
+            prefilter,
+            byte_type: PhantomData,
+        }
+    }
+
+    /// Iterate over the `(offset, data size)` of every entry, without
+    /// reading the entry data. Each frame's head and tail are read and
+    /// checked against each other; the data in between is skipped.
+    ///
+    /// Iteration stops at the end of the log or at the first frame that
+    /// doesn't check out.
+    pub fn offsets(&self) -> FrameOffsets<ByteType> {
+        FrameOffsets {
+            reader: BufOffsetReader::new(self.clone_file()),
+            next: 0,
+            end: self.end_of_file,
+            byte_type: PhantomData,
+        }
+    }
+
+    /// Iterate over the log from the first entry. The iterator reads
+    /// through its own clone of the file handle, so it doesn't get in the
+    /// way of `get` or `append` on the log.
+    pub fn iter(&self) -> Forward<OffsetLogIter<ByteType>> {
+        OffsetLogIter::new(self.clone_file()).forward_owned()
+    }
+
+    /// A `Read` of the log's entries as newline-delimited JSON.
+    pub fn json_lines_reader(&self) -> JsonLinesReader<Forward<OffsetLogIter<ByteType>>> {
+        JsonLinesReader::new(self.iter())
+    }
+
+    /// Iterate over the log from the last entry back to the first.
+    ///
+    /// Like `iter`, this stops at the first frame it can't read, so a
+    /// corrupt entry hides every entry before it without an error. Use
+    /// `iter_recovering` to find and step over corrupt regions.
+    pub fn rev_iter(&self) -> Backward<OffsetLogIter<ByteType>> {
+        self.bidir_iter_at_offset(self.end_of_file).backward_owned()
+    }
+
+    pub fn bidir_iter(&self) -> OffsetLogIter<ByteType> {
+        OffsetLogIter::new(self.clone_file())
+    }
+
+    pub fn bidir_iter_at_offset(&self, offset: u64) -> OffsetLogIter<ByteType> {
+        OffsetLogIter::with_starting_offset(self.clone_file(), offset)
+    }
+}
+
+// Errors can't be reported from here, so a log that has to know its last
+// sync worked should call `flush` itself before dropping.
+impl<ByteType> Drop for OffsetLog<ByteType> {
+    fn drop(&mut self) {
+        if self.read_only {
+            return;
+        }
+        if self.durability == Durability::Manual {
+            let _ = self.flush_writes();
+        } else {
+            let _ = self.flush();
+        }
+    }
+}
+
+impl<ByteType> FlumeLog for OffsetLog<ByteType> {
+    fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
+        self.inverse_body(self.read(seq_num)?.entry.data)
+    }
+
+    /// The offset of the last entry. For an existing log this is found
+    /// when the log is opened, by reading the last frame backward from the
+    /// end of the file.
+    fn latest(&self) -> Option<u64> {
+        self.last_offset
+    }
+
+    fn append(&mut self, buff: &[u8]) -> Result<u64, Error> {
+        let buff = self.transform_body(buff)?;
+
+        self.write_entry(buff.len(), |offset, dest| {
+            encode::<ByteType>(offset, &buff, dest)
+        })
+    }
+
+    /// Overwrite the data of the entry at `seq_num` with zeros. The framing
+    /// is left as it is, so the log can still be read through and the
+    /// entry's offset stays valid.
+    fn clear(&mut self, seq_num: u64) -> Result<(), Error> {
+        self.check_writable()?;
+        self.flush_writes()?;
+        let data_size = self.read(seq_num)?.entry.data.len();
+        let frame = Frame {
+            offset: seq_num,
+            data_size,
+        };
+        write_all_at(&self.file, &vec![0; data_size], frame.data_start())?;
+        Ok(())
+    }
+}
+
+/// A read-only window onto the byte range `[start, end)` of an `OffsetLog`.
+///
+/// Offsets are absolute, so an entry has the same offset in the sub-log as
+/// in the full log.
+pub struct SubLog<ByteType> {
+    file: File,
+    start: u64,
+    end: u64,
+    byte_type: PhantomData<ByteType>,
+}
+
+impl<ByteType> SubLog<ByteType> {
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn end(&self) -> u64 {
+        self.end
+    }
+
+    /// Read the entry at `offset`. An offset outside the sub log, or that
+    /// isn't the start of a frame, is an `InvalidOffset` error, as it is for
+    /// `OffsetLog::read`.
+    pub fn read(&self, offset: u64) -> Result<ReadResult, Error> {
+        let invalid = || -> Error { FlumeOffsetLogError::InvalidOffset { offset }.into() };
+        if offset < self.start || offset >= self.end {
+            return Err(invalid());
+        }
+        read_next::<ByteType, _>(offset, &self.file).map_err(|e| {
+            match e.downcast_ref::<FlumeOffsetLogError>() {
+                Some(_) => invalid(),
+                None => e,
+            }
+        })
+    }
+
+    pub fn get(&self, offset: u64) -> Result<Vec<u8>, Error> {
+        self.read(offset).map(|r| r.entry.data)
+    }
+
+    pub fn iter(&self) -> Result<impl Iterator<Item = LogEntry>, Error> {
+        let end = self.end;
+        let iter =
+            OffsetLogIter::<ByteType>::with_starting_offset(self.file.try_clone()?, self.start);
+        Ok(iter.forward_owned().take_while(move |e| e.offset < end))
+    }
+}
+
+/// A bidirectional iterator over the entries of an `OffsetLog`.
+///
+/// Reads go through a buffer (8 KiB by default). Each entry takes one read
+/// for its length and one for the rest of the frame. A read that isn't
+/// already in the buffer refills it from that offset. A frame larger than
+/// the buffer is read straight from the file and doesn't refill it. So with
+/// entries much smaller than the buffer, one refill serves many entries. With
+/// entries larger than it, every entry costs two reads of the file. Use
+/// `with_capacity` to size the buffer for the entries in the log.
+pub struct OffsetLogIter<ByteType> {
+    reader: BufOffsetReader<File>,
+    current: u64,
+    next: u64,
+    // Length of the file when the iterator was made, if it could be found.
+    file_length: Option<u64>,
+    byte_type: PhantomData<ByteType>,
+}
+
+impl<ByteType> OffsetLogIter<ByteType> {
+    pub fn new(file: File) -> OffsetLogIter<ByteType> {
+        OffsetLogIter::with_starting_offset(file, 0)
+    }
+
+    /// An iterator from the start of the log that reads through a buffer of
+    /// `capacity` bytes.
+    pub fn with_capacity(file: File, capacity: usize) -> OffsetLogIter<ByteType> {
+        OffsetLogIter::from_reader(file, 0, |f| BufOffsetReader::with_capacity(capacity, f))
+    }
+
+    /// An iterator that starts at `offset`. If `offset` isn't the start of
+    /// an entry, the iterator just yields nothing; use
+    /// `try_with_starting_offset` to catch offsets past the end of the file.
+    pub fn with_starting_offset(file: File, offset: u64) -> OffsetLogIter<ByteType> {
+        OffsetLogIter::from_reader(file, offset, BufOffsetReader::new)
+    }
+
+    fn from_reader<F>(file: File, offset: u64, reader: F) -> OffsetLogIter<ByteType>
+    where
+        F: FnOnce(File) -> BufOffsetReader<File>,
+    {
+        OffsetLogIter {
+            file_length: file.metadata().map(|m| m.len()).ok(),
+            reader: reader(file),
+            current: offset,
+            next: offset,
+            byte_type: PhantomData,
+        }
+    }
+
+    /// Like `with_starting_offset`, but fails if the file can't be stat'd
+    /// or `offset` is past its end. Useful when the offset comes from
+    /// outside, such as a saved resume point.
+    pub fn try_with_starting_offset(
+        file: File,
+        offset: u64,
+    ) -> Result<OffsetLogIter<ByteType>, Error> {
+        let file_length = file
+            .metadata()
+            .map_err(|error| FlumeOffsetLogError::StatFailed { error })?
+            .len();
+        if offset > file_length {
+            return Err(FlumeOffsetLogError::InvalidOffset { offset }.into());
+        }
+        Ok(OffsetLogIter::with_starting_offset(file, offset))
+    }
+
+    /// Bounds on the number of entries left going forward, in the style of
+    /// `Iterator::size_hint`. The upper bound is the bytes left in the file
+    /// divided by the size of an empty frame.
+    ///
+    /// The file length is taken when the iterator is made, so entries
+    /// appended after that aren't counted. `Forward` doesn't pass this on
+    /// as its own `size_hint`, so call it on the `OffsetLogIter`, for
+    /// example to size a `Vec` before collecting into it.
+    pub fn size_hint(&self) -> (usize, Option<usize>) {
+        let upper = self.file_length.and_then(|len| {
+            let remaining = len.saturating_sub(self.next);
+            usize::try_from(remaining / size_of_framing_bytes::<ByteType>() as u64).ok()
+        });
+        (0, upper)
+    }
+}
+
+impl<ByteType> IterAtOffset<Forward<OffsetLogIter<ByteType>>> for OffsetLog<ByteType> {
+    fn iter_at_offset(&self, offset: u64) -> Forward<OffsetLogIter<ByteType>> {
+        OffsetLogIter::with_starting_offset(self.clone_file(), offset).forward_owned()
+    }
+}
+
+impl<ByteType> BidirIterator for OffsetLogIter<ByteType> {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.current = self.next;
+        let r = read_next_mut::<ByteType, _>(self.current, &mut self.reader).ok()?;
+        self.next = r.next;
+        Some(r.entry)
+    }
+
+    fn prev(&mut self) -> Option<Self::Item> {
+        self.next = self.current;
+        let r = read_prev_mut::<ByteType, _>(self.current, &mut self.reader).ok()?;
+        self.current = r.entry.offset;
+        Some(r.entry)
+    }
+}
+
+/// A run of bytes that `RecoveringIter` couldn't read as frames, from the
+/// start of a bad frame up to the next good one or the end of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CorruptRegion {
+    pub start: u64,
+    pub end: u64,
+}
+
+pub struct RecoveringIter<ByteType> {
+    reader: BufOffsetReader<File>,
+    // For reading trailing sizes while scanning, without moving the
+    // reader's buffer away from the scan.
+    file: File,
+    next: u64,
+    end: u64,
+    buf: Vec<u8>,
+    // The frame found at `next` by the last resync, and the offset after it.
+    found: Option<(LogEntry, u64)>,
+    byte_type: PhantomData<ByteType>,
+}
+
+impl<ByteType> RecoveringIter<ByteType> {
+    // Read the frame at `offset`, returning its entry and the offset of the
+    // frame after it. Frames that would run past the end of the file are
+    // rejected before their data is read.
+    fn read_frame(&mut self, offset: u64) -> Result<(LogEntry, u64), Error> {
+        let reader = &mut self.reader;
+        let mut read_at = |b: &mut [u8], o| reader.read_at(b, o);
+
+        let frame = read_next_frame(offset, &mut read_at)?;
+        let frame_size = (size_of_framing_bytes::<ByteType>() + frame.data_size) as u64;
+        if frame_size > self.end - offset {
+            return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        }
+
+        let next = read_frame_into::<ByteType, _>(&frame, &mut read_at, &mut self.buf)?;
+        let entry = LogEntry {
+            offset,
+            data: self.buf[..frame.data_size].to_vec(),
+        };
+        Ok((entry, next))
+    }
+
+    // Find the first offset from `start` where a whole frame can be read,
+    // returning it along with the frame, or the end of the file if there's
+    // no such offset. The leading sizes are read in one pass through the
+    // buffered reader, and only a size that matches the trailing size after
+    // it is read as a frame.
+    fn resync(&mut self, start: u64) -> (u64, Option<(LogEntry, u64)>) {
+        let size_bytes = size_of::<u32>() as u64;
+        let framing = size_of_framing_bytes::<ByteType>() as u64;
+        let mut size = [0; size_of::<u32>()];
+
+        let mut offset = start;
+        while offset + framing <= self.end {
+            match self.reader.read_at(&mut size, offset) {
+                Ok(n) if n == size.len() => {}
+                _ => break,
+            }
+            let data_size = u64::from(u32::from_be_bytes(size));
+
+            if data_size <= self.end - offset - framing {
+                let mut trailer = [0; size_of::<u32>()];
+                let trailer_offset = offset + size_bytes + data_size;
+                let matches = match self.file.read_at(&mut trailer, trailer_offset) {
+                    Ok(n) => n == trailer.len() && trailer == size,
+                    Err(_) => false,
+                };
+                if matches {
+                    if let Ok(found) = self.read_frame(offset) {
+                        return (offset, Some(found));
+                    }
+                }
+            }
+            offset += 1;
+        }
+        (self.end, None)
+    }
+}
+
+impl<ByteType> Iterator for RecoveringIter<ByteType> {
+    type Item = Result<LogEntry, CorruptRegion>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+
+        let frame = match self.found.take() {
+            Some(found) => Ok(found),
+            None => self.read_frame(self.next),
+        };
+        match frame {
+            Ok((entry, next)) => {
+                self.next = next;
+                Some(Ok(entry))
+            }
+            Err(_) => {
+                let start = self.next;
+                let (resume, found) = self.resync(start + 1);
+                self.next = resume;
+                self.found = found;
+                Some(Err(CorruptRegion { start, end: resume }))
+            }
+        }
+    }
+}
+
+pub struct OffsetLogFilteredIter<ByteType, F> {
+    reader: BufOffsetReader<File>,
+    next: u64,
+    buf: Vec<u8>,
+    prefilter: F,
+    byte_type: PhantomData<ByteType>,
+}
+
+impl<ByteType, F> Iterator for OffsetLogFilteredIter<ByteType, F>
+where
+    F: Fn(&[u8]) -> bool,
+{
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let reader = &mut self.reader;
+        let mut read_at = |b: &mut [u8], o| reader.read_at(b, o);
+        loop {
+            let frame = read_next_frame(self.next, &mut read_at).ok()?;
+            self.next = read_frame_into::<ByteType, _>(&frame, &mut read_at, &mut self.buf).ok()?;
+
+            let data = &self.buf[..frame.data_size];
+            if (self.prefilter)(data) {
+                return Some(LogEntry {
+                    offset: frame.offset,
+                    data: data.to_vec(),
+                });
+            }
+        }
+    }
+}
+
+pub struct FrameOffsets<ByteType> {
+    reader: BufOffsetReader<File>,
+    next: u64,
+    end: u64,
+    byte_type: PhantomData<ByteType>,
+}
+
+impl<ByteType> FrameOffsets<ByteType> {
+    fn read_frame(&mut self) -> Option<Frame> {
+        let reader = &mut self.reader;
+        let mut read_at = |b: &mut [u8], o| reader.read_at(b, o);
+        let frame = read_next_frame(self.next, &mut read_at).ok()?;
+
+        let tail_size = size_of_frame_tail::<ByteType>();
+        let tail_start = frame.data_start() + frame.data_size as u64;
+        let next = tail_start + tail_size as u64;
+        if next > self.end {
+            return None;
+        }
+
+        let mut tail = [0; size_of::<u32>() + MAX_OFFSET_WIDTH];
+        let tail = tail.get_mut(..tail_size)?;
+        if read_at(tail, tail_start).ok()? < tail_size {
+            return None;
+        }
+        let sz = (&tail[..]).read_u32::<BigEndian>().ok()? as usize;
+        let stored = read_trailing_offset::<ByteType>(&tail[size_of::<u32>()..], next).ok()?;
+        if sz != frame.data_size || stored != next {
+            return None;
+        }
+
+        self.next = next;
+        Some(frame)
+    }
+}
+
+impl<ByteType> Iterator for FrameOffsets<ByteType> {
+    type Item = (u64, usize);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.next >= self.end {
+            return None;
+        }
+        match self.read_frame() {
+            Some(frame) => Some((frame.offset, frame.data_size)),
+            None => {
+                // Don't try the bad frame again.
+                self.next = self.end;
+                None
+            }
+        }
+    }
+}
+
+fn open_file<P: AsRef<Path>>(options: &OpenOptions, path: P) -> Result<File, Error> {
+    options.open(&path).map_err(|error| {
+        let path = path.as_ref().display().to_string();
+        FlumeOffsetLogError::OpenFailed { path, error }.into()
+    })
+}
+
+pub(crate) fn size_of_frame_tail<T>() -> usize {
+    size_of::<u32>() + size_of::<T>()
+}
+pub(crate) fn size_of_framing_bytes<T>() -> usize {
+    size_of::<u32>() * 2 + size_of::<T>()
+}
+
+// Writes the head and tail of a frame (everything but the data) into `dest`,
+// head first. Returns the offset of the next frame.
+fn encode_framing<T>(offset: u64, data_size: usize, dest: &mut BytesMut) -> u64 {
+    dest.put_u32(data_size as u32);
+    dest.put_u32(data_size as u32);
+    let next_offset = offset + (size_of_framing_bytes::<T>() + data_size) as u64;
+    put_offset::<T>(next_offset, dest);
+    next_offset
+}
+
+// Offsets are stored big-endian in the width of `T`. Widths over 8 bytes
+// are padded with leading zeros, since offsets are never more than a u64.
+fn put_offset<T>(offset: u64, dest: &mut BytesMut) {
+    let width = size_of::<T>();
+    if width > size_of::<u64>() {
+        (size_of::<u64>()..width).for_each(|_| dest.put_u8(0));
+        dest.put_u64(offset);
+    } else {
+        dest.put_uint(offset, width);
+    }
+}
+
+fn read_offset<T>(bytes: &[u8]) -> io::Result<u64> {
+    let width = size_of::<T>();
+    if width > size_of::<u64>() {
+        let (padding, mut rest) = bytes.split_at(width - size_of::<u64>());
+        if padding.iter().any(|b| *b != 0) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "offset too large",
+            ));
+        }
+        rest.read_u64::<BigEndian>()
+    } else {
+        (&bytes[..]).read_uint::<BigEndian>(width)
+    }
+}
+
+// The widest offset that can be read or written, that of a u128.
+const MAX_OFFSET_WIDTH: usize = size_of::<u128>();
+
+// The free functions here can be called with any `T`, not just an
+// `OffsetType`, so check the width before using it.
+fn check_offset_width<T>() -> Result<(), Error> {
+    let width = size_of::<T>();
+    if width > MAX_OFFSET_WIDTH {
+        return Err(FlumeOffsetLogError::UnsupportedOffsetWidth { width }.into());
+    }
+    Ok(())
+}
+
+// Whether `offset` can be stored in the width of `T`.
+fn check_offset_fits<T>(offset: u64) -> Result<(), Error> {
+    check_offset_width::<T>()?;
+    let bits = size_of::<T>() * 8;
+    if bits > 0 && bits < 64 && offset >> bits != 0 {
+        return Err(FlumeOffsetLogError::OffsetTooLarge { offset }.into());
+    }
+    Ok(())
+}
+
+fn write_all_vectored<W: Write>(w: &mut W, mut slices: &mut [IoSlice]) -> io::Result<()> {
+    // Skip any leading empty slices, so a batch of empty entries doesn't
+    // look like a failed write.
+    IoSlice::advance_slices(&mut slices, 0);
+    while !slices.is_empty() {
+        match w.write_vectored(slices) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole batch",
+                ))
+            }
+            Ok(n) => IoSlice::advance_slices(&mut slices, n),
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+// `write_at` may write only part of the buffer, so keep going until it's all
+// been written.
+pub(crate) fn write_all_at<W: OffsetWrite>(
+    w: &W,
+    mut buf: &[u8],
+    mut offset: u64,
+) -> io::Result<()> {
+    while !buf.is_empty() {
+        match w.write_at(buf, offset) {
+            Ok(0) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::WriteZero,
+                    "failed to write whole buffer",
+                ))
+            }
+            Ok(n) => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
+            Err(ref e) if e.kind() == io::ErrorKind::Interrupted => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(())
+}
+
+pub fn encode<T>(offset: u64, item: &[u8], dest: &mut BytesMut) -> Result<u64, Error> {
+    let chunk_size = size_of_framing_bytes::<T>() + item.len();
+    let next_offset = offset + chunk_size as u64;
+    check_offset_fits::<T>(next_offset)?;
+
+    dest.reserve(chunk_size);
+    dest.put_u32(item.len() as u32);
+    dest.put_slice(item);
+    dest.put_u32(item.len() as u32);
+    put_offset::<T>(next_offset, dest);
+    Ok(next_offset)
+}
+
+/// Like `encode`, but writes `tag` as the first byte of the entry data.
+pub fn encode_tagged<T>(
+    offset: u64,
+    tag: u8,
+    item: &[u8],
+    dest: &mut BytesMut,
+) -> Result<u64, Error> {
+    let data_size = item.len() + 1;
+    let chunk_size = size_of_framing_bytes::<T>() + data_size;
+    let next_offset = offset + chunk_size as u64;
+    check_offset_fits::<T>(next_offset)?;
+
+    dest.reserve(chunk_size);
+    dest.put_u32(data_size as u32);
+    dest.put_u8(tag);
+    dest.put_slice(item);
+    dest.put_u32(data_size as u32);
+    put_offset::<T>(next_offset, dest);
+    Ok(next_offset)
+}
+
+pub fn validate_entry<T>(offset: u64, data_size: usize, rest: &[u8]) -> Result<u64, Error> {
+    if rest.len() != data_size + size_of_frame_tail::<T>() {
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    }
+
+    let sz = (&rest[data_size..]).read_u32::<BigEndian>()? as usize;
+    if sz != data_size {
+        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+    }
+
+    let frame_end = offset + size_of::<u32>() as u64 + rest.len() as u64;
+    let next = read_trailing_offset::<T>(&rest[(data_size + size_of::<u32>())..], frame_end)?;
+
+    // `next` is the offset of the entry immediately following this one.
+    // Requiring an exact match catches reads that started part way into a
+    // frame but happened to find a plausible pair of lengths.
+    if next != frame_end {
+        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+    }
+    Ok(next)
+}
+
+// Logs with a zero-width ByteType (`OffsetLog<()>`) are framed as
+// [len, data, len], with no trailing offset. For those the frame end is
+// taken as given.
+fn read_trailing_offset<T>(bytes: &[u8], frame_end: u64) -> io::Result<u64> {
+    if size_of::<T>() == 0 {
+        Ok(frame_end)
+    } else {
+        read_offset::<T>(bytes)
+    }
+}
+
+/// Decode the entry at `offset` in an in-memory log, returning its data as
+/// a slice of `bytes` rather than a copy.
+pub fn read_slice<ByteType>(offset: u64, bytes: &[u8]) -> Result<&[u8], Error> {
+    let frame = read_next_frame(offset, &mut |b, o| bytes.read_at(b, o))?;
+    let data_start = frame.data_start() as usize;
+    let frame_end = data_start + frame.data_size + size_of_frame_tail::<ByteType>();
+    if frame_end > bytes.len() {
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    }
+
+    validate_entry::<ByteType>(frame.offset, frame.data_size, &bytes[data_start..frame_end])?;
+    Ok(&bytes[data_start..data_start + frame.data_size])
+}
+
+pub fn read_next<ByteType, R: OffsetRead>(offset: u64, r: &R) -> Result<ReadResult, Error> {
+    read_next_impl::<ByteType, _>(offset, |b, o| r.read_at(b, o))
+}
+
+pub fn read_next_mut<ByteType, R: OffsetReadMut>(
+    offset: u64,
+    r: &mut R,
+) -> Result<ReadResult, Error> {
+    read_next_impl::<ByteType, _>(offset, |b, o| r.read_at(b, o))
+}
+
+pub fn read_prev<ByteType, R: OffsetRead>(offset: u64, r: &R) -> Result<ReadResult, Error> {
+    read_prev_impl::<ByteType, _>(offset, |b, o| r.read_at(b, o))
+}
+
+pub fn read_prev_mut<ByteType, R: OffsetReadMut>(
+    offset: u64,
+    r: &mut R,
+) -> Result<ReadResult, Error> {
+    read_prev_impl::<ByteType, _>(offset, |b, o| r.read_at(b, o))
+}
+
+fn read_next_impl<ByteType, F>(offset: u64, mut read_at: F) -> Result<ReadResult, Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    let frame = read_next_frame(offset, &mut read_at)?;
+    read_entry::<ByteType, _>(&frame, &mut read_at)
+}
+
+fn read_prev_impl<ByteType, F>(offset: u64, mut read_at: F) -> Result<ReadResult, Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    let frame = read_prev_frame::<ByteType, _>(offset, &mut read_at)?;
+    read_entry::<ByteType, _>(&frame, &mut read_at)
+}
+
+fn read_next_frame<F>(offset: u64, read_at: &mut F) -> Result<Frame, Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    // Entry is [payload size: u32, payload, payload_size: u32, next_offset: ByteType]
+
+    const HEAD_SIZE: usize = size_of::<u32>();
+
+    let mut head_bytes = [0; HEAD_SIZE];
+    let n = read_at(&mut head_bytes, offset)?;
+    if n < HEAD_SIZE {
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    }
+
+    let data_size = (&head_bytes[..]).read_u32::<BigEndian>()? as usize;
+    Ok(Frame { offset, data_size })
+}
+
+fn read_prev_frame<ByteType, F>(offset: u64, mut read_at: F) -> Result<Frame, Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    let tail_size = size_of_frame_tail::<ByteType>(); // TODO: why can't this be const?
+
+    check_offset_width::<ByteType>()?;
+    let mut tmp = [0; size_of::<u32>() + MAX_OFFSET_WIDTH];
+    if tail_size as u64 > offset {
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    }
+
+    let n = read_at(&mut tmp[..tail_size], offset - tail_size as u64)?;
+    if n < tail_size {
+        return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    }
+
+    let data_size = (&tmp[..]).read_u32::<BigEndian>()? as usize;
+    if (size_of_framing_bytes::<ByteType>() + data_size) as u64 > offset {
+        return Err(FlumeOffsetLogError::CorruptLogFile {}.into());
+    }
+
+    let data_start = offset - tail_size as u64 - data_size as u64;
+
+    Ok(Frame {
+        offset: data_start - size_of::<u32>() as u64,
+        data_size,
+    })
+}
+
+fn read_entry<ByteType, F>(frame: &Frame, read_at: &mut F) -> Result<ReadResult, Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    let mut buf = Vec::new();
+    let next = read_frame_into::<ByteType, _>(frame, read_at, &mut buf)?;
+
+    // Chop the tail off of buf, so it only contains the entry data.
+    buf.truncate(frame.data_size);
+
+    Ok(ReadResult {
         entry: LogEntry {
             offset: frame.offset,
             data: buf,
         },
-        next: next,
+        next,
     })
 }
 
+// Reads the data and tail of `frame` into `buf`, returning the offset of the next frame.
+// The entry data is `buf[..frame.data_size]`.
+fn read_frame_into<ByteType, F>(
+    frame: &Frame,
+    read_at: &mut F,
+    buf: &mut Vec<u8>,
+) -> Result<u64, Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    // Entry is [payload size: u32, payload, payload_size: u32, next_offset: ByteType]
+    let tail_size = size_of_frame_tail::<ByteType>();
+    let to_read = frame.data_size + tail_size;
+
+    // Every byte is overwritten by the read below, so a buffer that's too
+    // small is replaced rather than resized, which would copy its old contents.
+    if buf.len() < to_read {
+        *buf = vec![0; to_read];
+    } else {
+        buf.truncate(to_read);
+    }
+
+    // A single read can come up short, so keep going until the frame is
+    // complete or the file runs out.
+    let mut n = 0;
+    while n < to_read {
+        match read_at(&mut buf[n..], frame.data_start() + n as u64)? {
+            0 => return Err(FlumeOffsetLogError::DecodeBufferSizeTooSmall {}.into()),
+            r => n += r,
+        }
+    }
+
+    validate_entry::<ByteType>(frame.offset, frame.data_size, buf)
+}
+
 // extern crate tempfile;
 #[cfg(test)]
 mod test {
@@ -365,6 +1970,7 @@ mod test {
     use bytes::BytesMut;
 
     use serde_json::{from_slice, Value};
+    use std::io::Read;
 
     extern crate tempfile;
     use self::tempfile::tempfile;
@@ -394,6 +2000,90 @@ mod test {
         )
     }
 
+    #[test]
+    fn simple_encode_u16() {
+        let mut buf = BytesMut::new();
+        let next = encode::<u16>(0, &[1, 2, 3, 4], &mut buf).unwrap();
+        assert_eq!(&buf[..], &[0, 0, 0, 4, 1, 2, 3, 4, 0, 0, 0, 4, 0, 14]);
+
+        let r = read_next::<u16, _>(0, &&buf[..]).unwrap();
+        assert_eq!((r.entry.data, r.next), (vec![1, 2, 3, 4], next));
+
+        // Offsets past 64 KiB don't fit.
+        let too_big = encode::<u16>(u16::MAX as u64, &[1], &mut BytesMut::new());
+        match too_big
+            .unwrap_err()
+            .downcast::<FlumeOffsetLogError>()
+            .unwrap()
+        {
+            FlumeOffsetLogError::OffsetTooLarge { offset } => assert_eq!(offset, 65546),
+            e => panic!("unexpected error: {}", e),
+        }
+    }
+
+    #[test]
+    fn simple_encode_u128() {
+        let mut buf = BytesMut::new();
+        let next = encode::<u128>(0, &[1, 2, 3, 4], &mut buf).unwrap();
+        let mut expected = vec![0, 0, 0, 4, 1, 2, 3, 4, 0, 0, 0, 4];
+        expected.extend_from_slice(&28u128.to_be_bytes());
+        assert_eq!(&buf[..], &expected[..]);
+
+        let r = read_next::<u128, _>(0, &&buf[..]).unwrap();
+        assert_eq!((r.entry.data, r.next), (vec![1, 2, 3, 4], next));
+        let r = read_prev::<u128, _>(next, &&buf[..]).unwrap();
+        assert_eq!(r.entry.offset, 0);
+    }
+
+    #[test]
+    fn unsupported_offset_width() {
+        let mut buf = BytesMut::new();
+        match encode::<[u8; 32]>(0, &[1, 2, 3], &mut buf)
+            .unwrap_err()
+            .downcast::<FlumeOffsetLogError>()
+            .unwrap()
+        {
+            FlumeOffsetLogError::UnsupportedOffsetWidth { width } => assert_eq!(width, 32),
+            e => panic!("unexpected error: {}", e),
+        }
+        assert!(buf.is_empty());
+
+        let bytes = [0u8; 64];
+        assert!(read_prev::<[u8; 32], _>(64, &&bytes[..]).is_err());
+    }
+
+    #[test]
+    fn u16_and_u128_logs() -> Result<(), Error> {
+        let mut log = OffsetLog::<u16>::from_file(tempfile()?)?;
+        let offsets = log.append_batch(&[&b"abc"[..], b"defg"])?;
+        let log = OffsetLog::<u16>::from_file(log.file.try_clone()?)?;
+        assert_eq!(log.latest(), Some(offsets[1]));
+        assert_eq!(
+            log.iter().map(|e| e.data).collect::<Vec<_>>(),
+            [b"abc".to_vec(), b"defg".to_vec()]
+        );
+
+        let mut log = OffsetLog::<u16>::from_file(tempfile()?)?;
+        let end = log.append(&vec![0; 65000])?;
+        assert!(log.append(&vec![0; 1000]).is_err());
+        assert!(log.append_batch(&[vec![0; 1000]]).is_err());
+        assert_eq!(log.latest(), Some(end));
+        assert_eq!(log.validate()?, 1);
+
+        let mut log = OffsetLog::<u128>::from_file(tempfile()?)?;
+        let offsets = log.append_batch(&[&b"abc"[..], b"defg"])?;
+        let h = log.append(b"hi")?;
+        let log = OffsetLog::<u128>::from_file(log.file.try_clone()?)?;
+        assert_eq!(log.latest(), Some(h));
+        assert_eq!(log.get(offsets[1])?, b"defg");
+        assert_eq!(
+            log.rev_iter().map(|e| e.offset).collect::<Vec<_>>(),
+            [h, offsets[1], offsets[0]]
+        );
+        assert_eq!(log.validate()?, 3);
+        Ok(())
+    }
+
     #[test]
     fn encode_multi() {
         let mut buf = BytesMut::with_capacity(32);
@@ -429,6 +2119,73 @@ mod test {
         )
     }
 
+    #[test]
+    fn encode_multi_length_only() -> Result<(), Error> {
+        let mut buf = BytesMut::new();
+        let next = encode::<()>(0, &[1, 2, 3, 4], &mut buf)?;
+        assert_eq!(next, 12);
+        encode::<()>(next, &[], &mut buf)?;
+        assert_eq!(
+            &buf[..],
+            &[0, 0, 0, 4, 1, 2, 3, 4, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0, 0]
+        );
+
+        let bytes = &buf[..];
+        let r1 = read_next::<(), _>(0, &bytes)?;
+        assert_eq!(&r1.entry.data, &[1, 2, 3, 4]);
+        assert_eq!(r1.next, 12);
+        let r2 = read_next::<(), _>(r1.next, &bytes)?;
+        assert!(r2.entry.data.is_empty());
+        assert_eq!(r2.next, 20);
+
+        let r3 = read_prev::<(), _>(r2.next, &bytes)?;
+        assert_eq!(r3.entry.offset, 12);
+        let r4 = read_prev::<(), _>(r3.entry.offset, &bytes)?;
+        assert_eq!(r4.entry.offset, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn length_only_log() -> Result<(), Error> {
+        let mut log = OffsetLog::<()>::from_file(tempfile()?)?;
+        assert_eq!(log.latest(), None);
+        log.append(b"abc")?;
+        let offsets = log.append_batch(&[&b"defg"[..], b""])?;
+        assert_eq!(offsets, &[11, 23]);
+        assert_eq!(log.latest(), Some(23));
+        assert_eq!(log.iter().count(), 3);
+        assert_eq!(log.rewrite_offsets()?, 0);
+
+        let log = OffsetLog::<()>::from_file(log.file.try_clone()?)?;
+        assert_eq!(log.latest(), Some(23));
+        assert_eq!(log.end(), 31);
+        let entries: Vec<Vec<u8>> = log.iter().map(|e| e.data).collect();
+        assert_eq!(entries, &[&b"abc"[..], b"defg", b""]);
+        let backward: Vec<u64> = log
+            .bidir_iter_at_offset(log.end())
+            .backward()
+            .map(|e| e.offset)
+            .collect();
+        assert_eq!(backward, &[23, 11, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn encode_tagged_entry() -> Result<(), Error> {
+        let mut buf = BytesMut::new();
+        let next = encode_tagged::<u32>(0, 9, &[1, 2, 3], &mut buf)?;
+        assert_eq!(next, 16);
+        assert_eq!(&buf[..], &[0, 0, 0, 4, 9, 1, 2, 3, 0, 0, 0, 4, 0, 0, 0, 16]);
+
+        let entry = read_next::<u32, _>(0, &&buf[..])?
+            .entry
+            .into_tagged()
+            .unwrap();
+        assert_eq!(entry.tag, 9);
+        assert_eq!(entry.data(), &[1, 2, 3]);
+        Ok(())
+    }
+
     #[test]
     fn simple() {
         let bytes: &[u8] = &[0, 0, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 8, 0, 0, 0, 20];
@@ -503,6 +2260,25 @@ mod test {
         assert_eq!(&r4.entry.data, &[1, 2, 3, 4, 5, 6, 7, 8]);
     }
 
+    #[test]
+    fn get_cow() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let offsets = log.append_batch(&[b"abc", b"def"])?;
+
+        let mut bytes = Vec::new();
+        log.file.seek(SeekFrom::Start(0))?;
+        log.file.read_to_end(&mut bytes)?;
+
+        for offset in offsets {
+            let owned = log.get_cow(offset)?;
+            assert!(matches!(owned, Cow::Owned(_)));
+            assert_eq!(owned, read_slice::<u32>(offset, &bytes)?);
+        }
+        assert!(read_slice::<u32>(0, &bytes[..bytes.len() - 1]).is_ok());
+        assert!(read_slice::<u32>(15, &bytes[..bytes.len() - 1]).is_err());
+        Ok(())
+    }
+
     #[test]
     fn read_incomplete_entry() {
         let bytes: &[u8] = &[0, 0, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 9, 0, 0, 0];
@@ -523,14 +2299,449 @@ mod test {
         let bytes: &[u8] = &[0, 0, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 9, 0, 0, 0, 20];
         let r = read_next::<u32, _>(0, &bytes);
 
-        assert!(r.is_err());
-    }
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn errors_with_bad_next_offset_value() {
+        let bytes: &[u8] = &[0, 0, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 8, 0, 0, 0, 16];
+        let r = read_next::<u32, _>(0, &bytes);
+        assert!(r.is_err());
+    }
+
+    #[test]
+    fn get_past_eof() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut log = OffsetLog::<u32>::from_file(file.try_clone()?)?;
+        let a = log.append(b"abc")?;
+        let b = log.append(b"defgh")?;
+        assert!(log.get(log.end()).is_err());
+        assert!(log.get(log.end() + 100).is_err());
+
+        // Cut the last frame short, as if a write had been interrupted.
+        file.set_len(log.end() - 3)?;
+        assert_eq!(log.get(a)?, b"abc");
+        assert!(log.get(b).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn iter_over_partial_head() -> Result<(), Error> {
+        // A crash part way through the first write can leave fewer bytes
+        // than a frame head.
+        for len in 1..4 {
+            let mut file = tempfile()?;
+            file.write_all(&[0, 0, 0, 3][..len])?;
+            assert_eq!(
+                OffsetLogIter::<u32>::new(file.try_clone()?)
+                    .forward()
+                    .count(),
+                0
+            );
+            assert!(OffsetLog::<u32>::from_file(file).is_err());
+        }
+
+        // The same, after an intact frame.
+        let file = tempfile()?;
+        let mut log = OffsetLog::<u32>::from_file(file.try_clone()?)?;
+        let a = log.append(b"abc")?;
+        write_all_at(&file, &[0, 0], log.end())?;
+        let offsets: Vec<u64> = OffsetLogIter::<u32>::new(file)
+            .forward()
+            .map(|e| e.offset)
+            .collect();
+        assert_eq!(offsets, [a]);
+        Ok(())
+    }
+
+    #[test]
+    fn subscribe_to_appends() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        log.append(b"before")?;
+
+        let early = log.subscribe();
+        let a = log.append(b"a")?;
+        let late = log.subscribe();
+        let batch = log.append_batch(&[b"b", b"c"])?;
+        assert_eq!(
+            early.try_iter().collect::<Vec<_>>(),
+            [a, batch[0], batch[1]]
+        );
+
+        // A dropped receiver is unsubscribed on the next append.
+        drop(early);
+        let d = log.append(b"d")?;
+        assert_eq!(log.subscribers.lock().unwrap().len(), 1);
+
+        let offsets: Vec<u64> = late.try_iter().collect();
+        assert_eq!(offsets, [batch[0], batch[1], d]);
+        assert_eq!(log.get(offsets[1])?, b"c");
+        Ok(())
+    }
+
+    #[test]
+    fn try_with_starting_offset() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        log.append(b"a")?;
+        let b = log.append(b"b")?;
+
+        let file = log.file.try_clone()?;
+        let mut iter = OffsetLogIter::<u32>::try_with_starting_offset(file, b)?;
+        assert_eq!(iter.next().map(|e| e.data), Some(b"b".to_vec()));
+
+        let file = log.file.try_clone()?;
+        let mut iter = OffsetLogIter::<u32>::try_with_starting_offset(file, log.end())?;
+        assert!(iter.next().is_none());
+
+        let file = log.file.try_clone()?;
+        let offset = log.end() + 1;
+        match OffsetLogIter::<u32>::try_with_starting_offset(file, offset) {
+            Err(e) => match e.downcast::<FlumeOffsetLogError>()? {
+                FlumeOffsetLogError::InvalidOffset { offset: o } => assert_eq!(o, offset),
+                e => panic!("unexpected error: {}", e),
+            },
+            Ok(_) => panic!("expected an error"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn iter_at_offset_yields_file_offsets() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let offsets = log.append_batch(&[&b"abc"[..], b"de", b"fghi", b"j"])?;
+
+        // Starting part way through, the offsets are still those in the file.
+        let entries: Vec<(u64, Vec<u8>)> = log
+            .iter_at_offset(offsets[2])
+            .map(|e| (e.offset, e.data))
+            .collect();
+        assert_eq!(
+            entries,
+            [(offsets[2], b"fghi".to_vec()), (offsets[3], b"j".to_vec())]
+        );
+        assert_eq!(log.get(entries[0].0)?, b"fghi");
+        Ok(())
+    }
+
+    #[test]
+    fn iter_with_capacity() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let entries: Vec<Vec<u8>> = (0..20u8).map(|i| vec![i; 37 * i as usize]).collect();
+        log.append_batch(&entries)?;
+
+        // Buffers smaller than the frames, smaller than the length field,
+        // and bigger than the whole log should all read the same entries.
+        for capacity in [1, 16, 100, 1 << 20] {
+            let file = log.file.try_clone()?;
+            let data: Vec<Vec<u8>> = OffsetLogIter::<u32>::with_capacity(file, capacity)
+                .forward()
+                .map(|e| e.data)
+                .collect();
+            assert_eq!(data, entries);
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn iter_frames_across_buffer_refills() -> Result<(), Error> {
+        // With the default 8 KiB buffer, frames of about 3 KB regularly
+        // start in one buffer load and end in the next.
+        let mut log = temp_offset_log();
+        let entries: Vec<Vec<u8>> = (0..30u8).map(|i| vec![i; 2990 + i as usize]).collect();
+        let offsets = log.append_batch(&entries)?;
+
+        let read: Vec<(u64, Vec<u8>)> = log.iter().map(|e| (e.offset, e.data)).collect();
+        let expected: Vec<(u64, Vec<u8>)> = offsets.into_iter().zip(entries).collect();
+        assert_eq!(read, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn flush_and_durability() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("durable.offset");
+
+        let mut log = OffsetLog::<u32>::new(&path)?;
+        let a = log.append(b"flushed")?;
+        log.flush()?;
+        log.set_durability(Durability::EveryWrite);
+        let b = log.append(b"synced")?;
+        let batch = log.append_batch(&[&b"in a"[..], b"batch"])?;
+        drop(log);
+
+        let log = OffsetLog::<u32>::open_read_only(&path)?;
+        assert_eq!(log.get(a)?, b"flushed");
+        assert_eq!(log.get(b)?, b"synced");
+        assert_eq!(log.get(batch[1])?, b"batch");
+        assert_eq!(log.latest(), Some(batch[1]));
+        Ok(())
+    }
+
+    #[test]
+    fn flush_on_drop() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log.offset");
+        let summary_path = dir.path().join("log.offset.summary");
+        let summary_end = || -> Result<u64, Error> {
+            let summary = std::fs::read(&summary_path)?;
+            Ok((&summary[16..24]).read_u64::<BigEndian>()?)
+        };
+
+        // Appends reach the file without a flush.
+        let mut log = OffsetLog::<u32>::open_with_summary(&path)?;
+        let a = log.append(b"abc")?;
+        drop(log);
+        let log = OffsetLog::<u32>::open_read_only(&path)?;
+        assert_eq!(log.get(a)?, b"abc");
+        drop(log);
+
+        // But only `OnDrop` brings the summary up to date.
+        let mut log = OffsetLog::<u32>::open_with_summary(&path)?;
+        assert_eq!(summary_end()?, log.end());
+        log.append(b"def")?;
+        let end = log.end();
+        drop(log);
+        assert!(summary_end()? < end);
+
+        let mut log = OffsetLog::<u32>::open_with_summary(&path)?;
+        log.set_durability(Durability::OnDrop);
+        let b = log.append(b"ghi")?;
+        let end = log.end();
+        drop(log);
+        assert_eq!(summary_end()?, end);
+
+        let log = OffsetLog::<u32>::open_with_summary(&path)?;
+        assert_eq!(log.get(b)?, b"ghi");
+        assert_eq!(log.len(), 3);
+        Ok(())
+    }
+
+    #[test]
+    fn write_buffer() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log.offset");
+        let file_len = || std::fs::metadata(&path).unwrap().len();
+
+        let mut log = OffsetLog::<u32>::new(&path)?;
+        log.set_write_buffer_capacity(64)?;
+        let a = log.append(b"abc")?;
+        let b = log.append_batch(&[&b"de"[..], b"fgh"])?;
+        assert_eq!(file_len(), 0);
+
+        // Reading writes the buffer out first.
+        assert_eq!(log.get(a)?, b"abc");
+        assert_eq!(file_len(), log.end());
+
+        // Entries bigger than the buffer are written straight away.
+        let c = log.append(b"i")?;
+        let d = log.append(&[7; 100])?;
+        assert_eq!(file_len(), log.end());
+
+        let e = log.append(b"jk")?;
+        let entries: Vec<u64> = log.iter().map(|e| e.offset).collect();
+        assert_eq!(entries, [a, b[0], b[1], c, d, e]);
+
+        // Dropping the log writes out whatever is still buffered.
+        let f = log.append(b"lmn")?;
+        assert!(file_len() < log.end());
+        drop(log);
+
+        let log = OffsetLog::<u32>::open_read_only(&path)?;
+        assert_eq!(log.latest(), Some(f));
+        assert_eq!(log.get(f)?, b"lmn");
+        assert_eq!(log.get_batch(&[e, b[1]])?, [&b"jk"[..], b"fgh"]);
+        assert_eq!(log.get(d)?, vec![7; 100]);
+        Ok(())
+    }
+
+    #[test]
+    fn write_buffer_many_appends() -> Result<(), Error> {
+        let mut buffered = temp_offset_log();
+        buffered.set_write_buffer_capacity(8 * 1024)?;
+        let mut unbuffered = temp_offset_log();
+
+        for i in 0..100_000u32 {
+            let item = i.to_be_bytes();
+            assert_eq!(buffered.append(&item)?, unbuffered.append(&item)?);
+        }
+        assert_eq!(buffered.len(), 100_000);
+        assert_eq!(buffered.content_hash()?, unbuffered.content_hash()?);
+        Ok(())
+    }
+
+    fn corrupt_at(log: &OffsetLog<u32>) -> u64 {
+        match log
+            .validate()
+            .unwrap_err()
+            .downcast::<FlumeOffsetLogError>()
+        {
+            Ok(FlumeOffsetLogError::CorruptAt { offset }) => offset,
+            e => panic!("unexpected result: {:?}", e),
+        }
+    }
+
+    #[test]
+    fn validate() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        assert_eq!(log.validate()?, 0);
+        let offsets = log.append_batch(&[&b"abc"[..], b"defg", b"hi", b"jklmn"])?;
+        assert_eq!(log.validate()?, 4);
+
+        // Flip a byte of the third entry's length.
+        log.file.write_at(&[0, 0, 1, 2], offsets[2])?;
+        assert_eq!(corrupt_at(&log), offsets[2]);
+        log.file.write_at(&[0, 0, 0, 2], offsets[2])?;
+        assert_eq!(log.validate()?, 4);
+
+        // A trailing offset that doesn't chain to the next frame.
+        log.file.write_at(&[0, 0, 0, 1], offsets[1] - 4)?;
+        assert_eq!(corrupt_at(&log), offsets[0]);
+        log.rewrite_offsets()?;
+        assert_eq!(log.validate()?, 4);
+
+        // A torn write at the end of the file.
+        log.file.set_len(log.end() - 1)?;
+        assert_eq!(corrupt_at(&log), offsets[3]);
+        Ok(())
+    }
+
+    #[test]
+    fn truncate_to() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let offsets = log.append_batch(&[&b"abc"[..], b"defg", b"hi", b"jklmn"])?;
+        assert_eq!(log.len(), 4);
+
+        for bad in [offsets[1] + 1, log.end() + 1] {
+            match log
+                .truncate_to(bad)
+                .unwrap_err()
+                .downcast::<FlumeOffsetLogError>()?
+            {
+                FlumeOffsetLogError::NotFrameBoundary { offset } => assert_eq!(offset, bad),
+                e => panic!("unexpected error: {}", e),
+            }
+        }
+
+        log.truncate_to(log.end())?;
+        assert_eq!(log.latest(), Some(offsets[3]));
+
+        // Corrupt the third entry, then cut the log back to just before it.
+        log.file.write_at(&[0, 0, 1, 2], offsets[2])?;
+        let corrupt = corrupt_at(&log);
+        log.truncate_to(corrupt)?;
+        assert_eq!(log.end(), offsets[2]);
+        assert_eq!(log.latest(), Some(offsets[1]));
+        assert_eq!(log.len(), 2);
+        let data: Vec<Vec<u8>> = log.iter().map(|e| e.data).collect();
+        assert_eq!(data, [b"abc".to_vec(), b"defg".to_vec()]);
+
+        let e = log.append(b"e")?;
+        assert_eq!(e, offsets[2]);
+        assert_eq!(log.validate()?, 3);
+
+        log.truncate_to(0)?;
+        assert_eq!((log.latest(), log.len()), (None, 0));
+        Ok(())
+    }
+
+    #[test]
+    fn iter_size_hint() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        assert_eq!(log.bidir_iter().size_hint(), (0, Some(0)));
+
+        let offsets = log.append_batch(&[&b""[..], b"a", b"bcdefghijkl", b""])?;
+        let mut iter = log.bidir_iter();
+        for remaining in (0..=offsets.len()).rev() {
+            let (lower, upper) = iter.size_hint();
+            assert!(lower <= remaining);
+            assert!(upper.unwrap() >= remaining);
+            iter.next();
+        }
+        assert_eq!(iter.size_hint(), (0, Some(0)));
+
+        let iter = log.bidir_iter_at_offset(offsets[2]);
+        assert!(iter.size_hint().1.unwrap() >= 2);
+        Ok(())
+    }
+
+    #[test]
+    fn get_batch() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let entries: Vec<Vec<u8>> = (0..10u8).map(|i| vec![i; i as usize * 3]).collect();
+        let offsets = log.append_batch(&entries)?;
+
+        let seqs = [offsets[7], offsets[2], offsets[9], offsets[2], offsets[0]];
+        let data = log.get_batch(&seqs)?;
+        let expected: Vec<Vec<u8>> = [7, 2, 9, 2, 0]
+            .iter()
+            .map(|&i| entries[i].clone())
+            .collect();
+        assert_eq!(data, expected);
+        assert!(log.get_batch(&[])?.is_empty());
+
+        match log
+            .get_batch(&[offsets[1], offsets[1] + 1])
+            .unwrap_err()
+            .downcast::<FlumeOffsetLogError>()?
+        {
+            FlumeOffsetLogError::InvalidOffset { offset } => assert_eq!(offset, offsets[1] + 1),
+            e => panic!("unexpected error: {}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn get_rejects_misaligned_offset() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let seq = log.append(b"{\"value\": 1}")?;
+        assert!(log.get(seq + 1).is_err());
+
+        // An entry whose data looks like a frame of its own, with a trailing
+        // offset that's larger than the real end of that frame.
+        let seq = log.append(&[0, 0, 0, 1, 9, 0, 0, 0, 1, 0, 0, 0, 100])?;
+        assert!(log.get(seq).is_ok());
+        assert!(log.get(seq + 4).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn get_invalid_offset() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        log.append(b"abc")?;
+        // An entry whose data starts with an enormous length.
+        let seq = log.append(&[0xff, 0xff, 0xff, 0xf0, 1, 2, 3])?;
+
+        for offset in [1, seq + 4, log.end(), log.end() + 100, u64::MAX] {
+            match log
+                .get(offset)
+                .unwrap_err()
+                .downcast::<FlumeOffsetLogError>()?
+            {
+                FlumeOffsetLogError::InvalidOffset { offset: o } => assert_eq!(o, offset),
+                e => panic!("unexpected error: {}", e),
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn detect_offset_width() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+
+        let path = dir.path().join("u32.offset");
+        OffsetLog::<u32>::new(&path)?.append_batch(&[b"abc", b"def"])?;
+        assert_eq!(detect_width(&path)?, OffsetWidth::U32);
+
+        let path = dir.path().join("u64.offset");
+        OffsetLog::<u64>::new(&path)?.append_batch(&[b"abc", b"def"])?;
+        assert_eq!(detect_width(&path)?, OffsetWidth::U64);
+
+        let path = dir.path().join("empty.offset");
+        OffsetLog::<u64>::new(&path)?;
+        assert!(detect_width(&path).is_err());
 
-    #[test]
-    fn errors_with_bad_next_offset_value() {
-        let bytes: &[u8] = &[0, 0, 0, 8, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0, 8, 0, 0, 0, 16];
-        let r = read_next::<u32, _>(0, &bytes);
-        assert!(r.is_err());
+        assert_eq!(detect_width("./db/test.offset")?, OffsetWidth::U32);
+        Ok(())
     }
 
     #[test]
@@ -549,6 +2760,15 @@ mod test {
         assert_eq!(result, 0);
     }
 
+    #[test]
+    fn end_and_latest_on_open() -> Result<(), Error> {
+        let log = OffsetLog::<u32>::new("./db/test.offset")?;
+        assert_eq!(log.latest(), Some(207));
+        assert_eq!(log.end(), log.file.metadata()?.len());
+        assert_eq!(log.read(207)?.next, log.end());
+        Ok(())
+    }
+
     #[test]
     fn open_read_only() {
         let mut log = OffsetLog::<u32>::open_read_only("./db/test.offset").unwrap();
@@ -567,6 +2787,58 @@ mod test {
         assert!(log.append(&[1, 2, 3, 4]).is_err());
     }
 
+    #[test]
+    fn read_only_rejects_writes() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log.offset");
+        let mut writer = OffsetLog::<u32>::new(&path)?;
+        let a = writer.append(b"abc")?;
+        let b = writer.append(b"defgh")?;
+        drop(writer);
+        let length = std::fs::metadata(&path)?.len();
+
+        let mut log = OffsetLog::<u32>::open_read_only(&path)?;
+        assert!(log.is_read_only());
+        assert_eq!(log.get(a)?, b"abc");
+        assert_eq!(log.latest(), Some(b));
+        assert_eq!(log.iter().map(|e| e.offset).collect::<Vec<_>>(), vec![a, b]);
+
+        let is_read_only = |e: Error| {
+            matches!(
+                e.downcast::<FlumeOffsetLogError>(),
+                Ok(FlumeOffsetLogError::ReadOnly {})
+            )
+        };
+        assert!(is_read_only(log.append(b"x").unwrap_err()));
+        assert!(is_read_only(log.append_batch(&[b"x"]).unwrap_err()));
+        assert!(is_read_only(log.clear(a).unwrap_err()));
+        assert!(is_read_only(log.truncate_to(b).unwrap_err()));
+        assert!(is_read_only(log.rewrite_offsets().unwrap_err()));
+
+        assert_eq!(std::fs::metadata(&path)?.len(), length);
+        assert_eq!(log.latest(), Some(b));
+        assert_eq!(log.get(a)?, b"abc");
+        Ok(())
+    }
+
+    #[test]
+    fn open_errors() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("missing").join("log.offset");
+
+        let err = OffsetLog::<u32>::new(&path).err().unwrap();
+        match err.downcast::<FlumeOffsetLogError>()? {
+            FlumeOffsetLogError::OpenFailed { path: p, error } => {
+                assert_eq!(p, path.display().to_string());
+                assert_eq!(error.kind(), io::ErrorKind::NotFound);
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+
+        assert!(OffsetLog::<u32>::open_read_only(dir.path().join("nope")).is_err());
+        Ok(())
+    }
+
     #[test]
     fn write_to_a_file() -> Result<(), Error> {
         let test_vec = b"{\"value\": 1}";
@@ -593,11 +2865,7 @@ mod test {
     fn batch_write_to_a_file() -> Result<(), Error> {
         let test_vec: &[u8] = b"{\"value\": 1}";
 
-        let mut test_vecs = Vec::new();
-
-        for _ in 0..100 {
-            test_vecs.push(test_vec);
-        }
+        let test_vecs = vec![test_vec; 100];
 
         let mut offset_log = temp_offset_log();
         let result = offset_log
@@ -613,10 +2881,7 @@ mod test {
             })
             .and_then(|val| from_slice(&val).map_err(|err| err.into()))
             .map(|val: Value| match val["value"] {
-                Value::Number(ref num) => {
-                    let result = num.as_u64().unwrap();
-                    result
-                }
+                Value::Number(ref num) => num.as_u64().unwrap(),
                 _ => panic!(),
             })
             .unwrap();
@@ -624,11 +2889,313 @@ mod test {
         Ok(())
     }
 
+    #[test]
+    fn batch_offsets_survive_reopen() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut log = OffsetLog::<u64>::from_file(file.try_clone()?)?;
+        log.append(b"first")?;
+
+        let batch: Vec<Vec<u8>> = (0..50u8).map(|i| vec![i; i as usize * 3]).collect();
+        let offsets = log.append_batch(&batch)?;
+        assert_eq!(offsets[0], log.read(0)?.next);
+
+        let reopened = OffsetLog::<u64>::from_file(file)?;
+        for (offset, data) in offsets.iter().zip(batch.iter()) {
+            assert_eq!(&reopened.get(*offset)?, data);
+        }
+        assert_eq!(reopened.latest(), offsets.last().cloned());
+        assert_eq!(reopened.end(), log.end());
+        Ok(())
+    }
+
+    #[test]
+    fn batch_write_matches_concatenated_encode() -> Result<(), Error> {
+        let test_vecs: Vec<Vec<u8>> = (0..2000u32)
+            .map(|i| format!("{{\"value\": {}}}", i).into_bytes())
+            .chain(std::iter::once(vec![]))
+            .collect();
+
+        let mut log = temp_offset_log();
+        log.append(b"first")?;
+        let offsets = log.append_batch(&test_vecs)?;
+
+        let mut expected = BytesMut::new();
+        let mut offset = encode::<u32>(0, b"first", &mut expected)?;
+        for (v, o) in test_vecs.iter().zip(offsets.iter()) {
+            assert_eq!(*o, offset);
+            offset = encode::<u32>(offset, v, &mut expected)?;
+        }
+        assert_eq!(log.end(), offset);
+        assert_eq!(log.latest(), offsets.last().cloned());
+
+        let mut written = Vec::new();
+        log.file.seek(SeekFrom::Start(0))?;
+        log.file.read_to_end(&mut written)?;
+        assert_eq!(&written[..], &expected[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn append_iter() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let first = log.append(b"first")?;
+
+        let items = (0..1000u32).map(|i| vec![i as u8; (i % 200) as usize]);
+        let offsets = log.append_iter(items)?;
+        assert_eq!(offsets.len(), 1000);
+        assert_eq!(offsets[0], log.read(first)?.next);
+        assert_eq!(log.latest(), offsets.last().cloned());
+
+        let entries: Vec<LogEntry> = log.iter().skip(1).collect();
+        assert_eq!(
+            entries.iter().map(|e| e.offset).collect::<Vec<_>>(),
+            offsets
+        );
+        for (i, entry) in entries.iter().enumerate() {
+            assert_eq!(entry.data, vec![i as u8; i % 200]);
+        }
+
+        // Borrowed slices work too, and nothing is written for no items.
+        let end = log.end();
+        assert!(log.append_iter(Vec::<&[u8]>::new())?.is_empty());
+        assert_eq!(log.end(), end);
+        let offsets = log.append_iter(vec![&b"abc"[..], b"de"])?;
+        assert_eq!(log.get(offsets[1])?, b"de");
+
+        let end = log.end();
+        let offsets = log.append_iter((0..20_000).map(|_| b""))?;
+        assert_eq!(offsets.len(), 20_000);
+        assert_eq!(log.end(), end + 20_000 * 12);
+        Ok(())
+    }
+
+    #[test]
+    fn offsets() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        assert_eq!(log.offsets().count(), 0);
+
+        for i in 0..500u32 {
+            log.append(&vec![i as u8; (i * 37 % 1000) as usize])?;
+        }
+        let expected: Vec<(u64, usize)> = log.iter().map(|e| (e.offset, e.data.len())).collect();
+        assert_eq!(log.offsets().collect::<Vec<_>>(), expected);
+
+        // Corrupt the trailing length of the 100th frame.
+        let (offset, size) = expected[100];
+        log.file.write_at(&[0xff; 4], offset + 4 + size as u64)?;
+        assert_eq!(log.offsets().collect::<Vec<_>>(), &expected[..100]);
+
+        // And a frame running past the end of the log.
+        log.file.write_at(&[0xff; 4], expected[50].0)?;
+        assert_eq!(log.offsets().count(), 50);
+
+        let mut log = OffsetLog::<u16>::from_file(tempfile()?)?;
+        let offsets = log.append_batch(&[&b"abc"[..], b"", b"de"])?;
+        let expected = vec![(offsets[0], 3), (offsets[1], 0), (offsets[2], 2)];
+        assert_eq!(log.offsets().collect::<Vec<_>>(), expected);
+        Ok(())
+    }
+
+    #[test]
+    fn append_batch_atomic() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log.offset");
+
+        let mut log = OffsetLog::<u32>::open_with_commit_marker(&path)?;
+        log.append(b"abc")?;
+        log.append_batch_atomic(&[b"def", b"ghi"])?;
+        let committed = log.end();
+
+        // Simulate a crash after the batch was written but before the
+        // commit marker was updated.
+        log.write_batch(&[b"jkl", b"mno"])?;
+        assert!(log.end() > committed);
+        drop(log);
+
+        let log = OffsetLog::<u32>::open_with_commit_marker(&path)?;
+        assert_eq!(log.end(), committed);
+        let entries: Vec<Vec<u8>> = log.iter().map(|e| e.data).collect();
+        assert_eq!(entries, &[b"abc", b"def", b"ghi"]);
+
+        assert!(temp_offset_log().append_batch_atomic(&[b"abc"]).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn commit_marker_follows_data_on_disk() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log.offset");
+        let marker_path = dir.path().join("log.offset.committed");
+        let read_marker = || -> Result<u64, Error> {
+            Ok((&std::fs::read(&marker_path)?[..]).read_u64::<BigEndian>()?)
+        };
+
+        let mut log = OffsetLog::<u32>::open_with_commit_marker(&path)?;
+        log.set_write_buffer_capacity(1024)?;
+        log.append(b"abc")?;
+        log.append_batch(&[b"def", b"ghi"])?;
+        assert_eq!(read_marker()?, std::fs::metadata(&path)?.len());
+        assert_eq!(read_marker()?, log.end());
+        drop(log);
+
+        // A marker past the end of the file means committed data is gone.
+        let length = std::fs::metadata(&path)?.len();
+        std::fs::write(&marker_path, (length + 10).to_be_bytes())?;
+        let err = OffsetLog::<u32>::open_with_commit_marker(&path)
+            .err()
+            .unwrap();
+        match err.downcast::<FlumeOffsetLogError>()? {
+            FlumeOffsetLogError::CommitMarkerPastEnd {
+                committed,
+                length: l,
+            } => {
+                assert_eq!((committed, l), (length + 10, length));
+            }
+            e => panic!("unexpected error: {}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn body_transform() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        log.set_body_transform(Box::new(|b| Ok(b.to_ascii_uppercase())));
+
+        let a = log.append(b"hello")?;
+        let b = log.append_batch(&[&b"flume"[..], b"db"])?;
+        assert_eq!(log.get(a)?, b"HELLO");
+        assert_eq!(log.get(b[1])?, b"DB");
+
+        let stored: Vec<Vec<u8>> = log.iter().map(|e| e.data).collect();
+        assert_eq!(stored, &[&b"HELLO"[..], b"FLUME", b"DB"]);
+
+        log.set_body_inverse(Box::new(|b| Ok(b.to_ascii_lowercase())));
+        assert_eq!(log.get(b[0])?, b"flume");
+        Ok(())
+    }
+
+    #[test]
+    fn summary() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("log.offset");
+        let summary_path = dir.path().join("log.offset.summary");
+
+        let mut log = OffsetLog::<u32>::open_with_summary(&path)?;
+        assert_eq!(log.len(), 0);
+        log.append(b"abc")?;
+        log.append_batch(&[b"def", b"ghi"])?;
+        assert_eq!(log.len(), 3);
+        log.write_summary()?;
+        let latest = log.latest();
+        drop(log);
+
+        // A summary that checks out is trusted as-is, without a scan.
+        let mut summary = std::fs::read(&summary_path)?;
+        summary[7] = 42;
+        let checksum = crc32(&summary[..24]);
+        summary[24..].copy_from_slice(&checksum.to_be_bytes());
+        std::fs::write(&summary_path, &summary)?;
+
+        let log = OffsetLog::<u32>::open_with_summary(&path)?;
+        assert_eq!(log.len(), 42);
+        assert_eq!(log.latest(), latest);
+        drop(log);
+
+        // A tampered summary fails its checksum and triggers a rescan.
+        summary[7] = 43;
+        std::fs::write(&summary_path, &summary)?;
+        let mut log = OffsetLog::<u32>::open_with_summary(&path)?;
+        assert_eq!(log.len(), 3);
+
+        // So does a summary that doesn't match the length of the log.
+        log.append(b"jkl")?;
+        drop(log);
+        let log = OffsetLog::<u32>::open_with_summary(&path)?;
+        assert_eq!(log.len(), 4);
+        Ok(())
+    }
+
+    #[test]
+    fn len() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut log = OffsetLog::<u32>::from_file(file.try_clone()?)?;
+        assert!(log.is_empty());
+        assert_eq!(log.len(), 0);
+
+        for i in 0..7u8 {
+            log.append(&[i])?;
+        }
+        log.append_batch(&[b"a", b"b", b"c"])?;
+        assert!(!log.is_empty());
+        assert_eq!(log.len(), 10);
+
+        // A fresh log scans once, then keeps counting appends.
+        let mut reopened = OffsetLog::<u32>::from_file(file)?;
+        assert_eq!(reopened.cached_len(), None);
+        assert_eq!(reopened.len(), 10);
+        assert_eq!(reopened.cached_len(), Some(10));
+        reopened.append(b"d")?;
+        reopened.append_batch(&[b"e", b"f"])?;
+        assert_eq!(reopened.cached_len(), Some(13));
+        assert_eq!(reopened.len(), reopened.iter().count() as u64);
+        Ok(())
+    }
+
+    #[test]
+    fn append_reuses_write_buffer() -> Result<(), Error> {
+        let big = vec![7; 1000];
+        let mut log = temp_offset_log();
+        let mut expected = BytesMut::new();
+
+        let mut offset = 0;
+        offset = encode::<u32>(offset, &big, &mut expected)?;
+        log.append(&big)?;
+        let buffer = log.tmp_buffer.as_ptr();
+
+        for i in 0..100u32 {
+            let entry = i.to_be_bytes();
+            offset = encode::<u32>(offset, &entry, &mut expected)?;
+            log.append(&entry)?;
+            assert_eq!(log.tmp_buffer.as_ptr(), buffer);
+        }
+        let batch = [&b"abc"[..], b"def"];
+        for entry in batch.iter() {
+            offset = encode::<u32>(offset, entry, &mut expected)?;
+        }
+        log.append_batch(&batch)?;
+        assert_eq!(log.tmp_buffer.as_ptr(), buffer);
+        assert_eq!(log.end(), offset);
+
+        let mut written = Vec::new();
+        log.file.seek(SeekFrom::Start(0))?;
+        log.file.read_to_end(&mut written)?;
+        assert_eq!(&written[..], &expected[..]);
+        Ok(())
+    }
+
+    #[test]
+    fn append_returns_entry_offset() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let sizes = [0, 1, 17, 4096, 65_537, 1 << 20, 3];
+
+        let offsets = sizes
+            .iter()
+            .map(|&size| log.append(&vec![size as u8; size]))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (&offset, &size) in offsets.iter().zip(sizes.iter()) {
+            assert_eq!(log.get(offset)?, vec![size as u8; size]);
+        }
+        assert_eq!(offsets[0], 0);
+        assert_eq!(log.latest(), offsets.last().cloned());
+        Ok(())
+    }
+
     #[test]
     fn arbitrary_read_and_write_to_a_file() -> Result<(), Error> {
         let mut offset_log = temp_offset_log();
 
-        let data_to_write = vec![b"{\"value\": 1}", b"{\"value\": 2}", b"{\"value\": 3}"];
+        let data_to_write = [b"{\"value\": 1}", b"{\"value\": 2}", b"{\"value\": 3}"];
 
         let seqs: Vec<u64> = data_to_write
             .iter()
@@ -641,10 +3208,7 @@ mod test {
             .map(|seq| offset_log.get(*seq).unwrap())
             .map(|val| from_slice(&val).unwrap())
             .map(|val: Value| match val["value"] {
-                Value::Number(ref num) => {
-                    let result = num.as_u64().unwrap();
-                    result
-                }
+                Value::Number(ref num) => num.as_u64().unwrap(),
                 _ => panic!(),
             })
             .sum();
@@ -663,10 +3227,7 @@ mod test {
             .map(|val| val.data)
             .map(|val| from_slice(&val).unwrap())
             .map(|val: Value| match val["value"] {
-                Value::Number(ref num) => {
-                    let result = num.as_u64().unwrap();
-                    result
-                }
+                Value::Number(ref num) => num.as_u64().unwrap(),
                 _ => panic!(),
             })
             .sum();
@@ -674,6 +3235,403 @@ mod test {
         assert_eq!(sum, 10);
     }
 
+    #[test]
+    fn iter_u64_log() -> Result<(), Error> {
+        let mut log = OffsetLog::<u64>::from_file(tempfile()?)?;
+        let offsets = log.append_batch(&[&b"abc"[..], b"", b"defgh"])?;
+
+        let forward: Vec<u64> = log.iter().map(|e| e.offset).collect();
+        assert_eq!(forward, offsets);
+        let backward: Vec<Vec<u8>> = log
+            .bidir_iter_at_offset(log.end())
+            .backward()
+            .map(|e| e.data)
+            .collect();
+        assert_eq!(backward, [&b"defgh"[..], b"", b"abc"]);
+        Ok(())
+    }
+
+    #[test]
+    fn iter_leaves_log_usable() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        for i in 1..=5 {
+            log.append(format!("{{\"value\": {}}}", i).as_bytes())?;
+        }
+
+        let mut iter = log.iter();
+        let first = iter.next().unwrap();
+
+        // The iterator reads from its own handle, so appends and gets on the
+        // log still land where they should.
+        let sixth = log.append(b"{\"value\": 6}")?;
+        assert_eq!(log.get(first.offset)?, first.data);
+        assert_eq!(log.get(sixth)?, b"{\"value\": 6}");
+
+        let sum: u64 = log
+            .iter()
+            .map(|e| from_slice(&e.data).unwrap())
+            .map(|val: Value| val["value"].as_u64().unwrap())
+            .sum();
+        assert_eq!(sum, 21);
+        Ok(())
+    }
+
+    #[test]
+    fn tail() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        assert!(log.tail(3)?.is_empty());
+
+        let offsets = (0..10)
+            .map(|i| log.append(format!("entry {}", i).as_bytes()))
+            .collect::<Result<Vec<u64>, Error>>()?;
+
+        let last_three = log.tail(3)?;
+        assert_eq!(
+            last_three,
+            vec![
+                (offsets[7], b"entry 7".to_vec()),
+                (offsets[8], b"entry 8".to_vec()),
+                (offsets[9], b"entry 9".to_vec()),
+            ]
+        );
+
+        let all = log.tail(20)?;
+        assert_eq!(all.len(), 10);
+        assert_eq!(all[0], (0, b"entry 0".to_vec()));
+        Ok(())
+    }
+
+    #[test]
+    fn rewrite_offsets() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let offsets = log.append_batch(&[b"abc", b"def", b"ghi"])?;
+        assert_eq!(log.rewrite_offsets()?, 0);
+
+        // Zero out the trailing offset of the first and last entries.
+        log.file.write_at(&[0, 0, 0, 0], offsets[1] - 4)?;
+        log.file.write_at(&[0, 0, 0, 0], log.end() - 4)?;
+        assert!(log
+            .bidir_iter_at_offset(log.end())
+            .backward()
+            .next()
+            .is_none());
+
+        assert_eq!(log.rewrite_offsets()?, 2);
+        let backward: Vec<u64> = log
+            .bidir_iter_at_offset(log.end())
+            .backward()
+            .map(|e| e.offset)
+            .collect();
+        assert_eq!(backward, &[offsets[2], offsets[1], offsets[0]]);
+        Ok(())
+    }
+
+    #[test]
+    fn sub_log() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let offsets = log.append_batch(&[b"a", b"b", b"c", b"d", b"e"])?;
+
+        let sub = log.sub_log(offsets[1], offsets[4])?;
+        let entries: Vec<Vec<u8>> = sub.iter()?.map(|e| e.data).collect();
+        assert_eq!(entries, &[b"b", b"c", b"d"]);
+
+        assert_eq!(sub.get(offsets[2])?, b"c");
+        for &offset in &[offsets[0], offsets[2] + 1, offsets[4]] {
+            let err = sub.get(offset).unwrap_err();
+            match err.downcast::<FlumeOffsetLogError>()? {
+                FlumeOffsetLogError::InvalidOffset { offset: o } => assert_eq!(o, offset),
+                e => panic!("unexpected error: {}", e),
+            }
+        }
+
+        assert!(log.sub_log(offsets[1] + 1, offsets[4]).is_err());
+        assert!(log.sub_log(offsets[1], offsets[4] - 1).is_err());
+        assert!(log.sub_log(offsets[3], offsets[1]).is_err());
+
+        assert_eq!(log.sub_log(0, log.end())?.iter()?.count(), 5);
+        Ok(())
+    }
+
+    #[test]
+    fn json_lines_reader() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        log.append(b"{\"value\": 1}")?;
+        log.append(b"{\"value\": 2}")?;
+        log.append(b"{\"value\": 3}")?;
+
+        let mut out = String::new();
+        log.json_lines_reader().read_to_string(&mut out)?;
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(
+            lines,
+            &["{\"value\": 1}", "{\"value\": 2}", "{\"value\": 3}"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn content_hash() -> Result<(), Error> {
+        let mut a = temp_offset_log();
+        let mut b = temp_offset_log();
+        assert_eq!(a.content_hash()?, b.content_hash()?);
+
+        a.append_batch(&[b"abc", b"def"])?;
+        b.append_batch(&[b"abc", b"def"])?;
+        assert_eq!(a.content_hash()?, b.content_hash()?);
+
+        let seq = a.append(b"ghi")?;
+        b.append(b"ghj")?;
+        assert_ne!(a.content_hash()?, b.content_hash()?);
+        assert_eq!(
+            a.content_hash_up_to(seq - 1)?,
+            b.content_hash_up_to(seq - 1)?
+        );
+
+        // Same bytes, split into entries differently
+        let mut c = temp_offset_log();
+        c.append_batch(&[&b"ab"[..], b"cdef", b"ghi"])?;
+        assert_ne!(a.content_hash()?, c.content_hash()?);
+        Ok(())
+    }
+
+    #[test]
+    fn find_first() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let offsets = log.append_batch(&[b"abc", b"def", b"ghi", b"def"])?;
+
+        let checked = std::cell::Cell::new(0);
+        let found = log.find_first(|data| {
+            checked.set(checked.get() + 1);
+            data == b"def"
+        })?;
+        assert_eq!(found, Some((offsets[1], b"def".to_vec())));
+        assert_eq!(checked.get(), 2);
+
+        assert_eq!(log.find_first(|data| data == b"xyz")?, None);
+        Ok(())
+    }
+
+    #[test]
+    fn tagged_entries() -> Result<(), Error> {
+        const DATA: u8 = 0;
+        const CONTROL: u8 = 1;
+
+        let mut log = temp_offset_log();
+        let first = log.append_tagged(DATA, b"abc")?;
+        log.append_tagged(CONTROL, b"checkpoint")?;
+        log.append_tagged(DATA, b"def")?;
+        let empty = log.append_tagged(CONTROL, b"")?;
+
+        let entry = log.read_tagged(first)?;
+        assert_eq!((entry.tag, entry.data()), (DATA, &b"abc"[..]));
+        assert_eq!(log.read_tagged(empty)?.data(), b"");
+
+        let data: Vec<Vec<u8>> = log.iter_tagged(DATA).map(|e| e.data().to_vec()).collect();
+        assert_eq!(data, &[b"abc", b"def"]);
+        assert_eq!(log.iter_tagged(CONTROL).count(), 2);
+
+        let untagged = log.append(b"")?;
+        assert!(log.read_tagged(untagged).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn iter_filtered() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let bodies: &[&[u8]] = &[
+            br#"{"type":"post","text":"a"}"#,
+            br#"{"type":"vote"}"#,
+            br#"{"type":"post","text":"b"}"#,
+            br#"{"type":"contact"}"#,
+        ];
+        let offsets = bodies
+            .iter()
+            .map(|b| log.append(b))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let is_post = |b: &[u8]| b.windows(13).any(|w| w == br#""type":"post""#);
+        let pairs = |e: LogEntry| (e.offset, e.data);
+        let posts: Vec<_> = log.iter_filtered(is_post).map(pairs).collect();
+        let expected: Vec<_> = log.iter().filter(|e| is_post(&e.data)).map(pairs).collect();
+        assert_eq!(posts, expected);
+        assert_eq!(posts.len(), 2);
+        assert_eq!((posts[0].0, posts[1].0), (offsets[0], offsets[2]));
+
+        assert_eq!(log.iter_filtered(|_| true).count(), 4);
+        assert_eq!(log.iter_filtered(|_| false).count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn clear() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let a = log.append(b"abc")?;
+        let b = log.append(b"defgh")?;
+        let c = log.append(b"ij")?;
+
+        log.clear(b)?;
+        assert_eq!(log.get(a)?, b"abc");
+        assert_eq!(log.get(b)?, &[0; 5]);
+        assert_eq!(log.get(c)?, b"ij");
+        assert_eq!(log.iter().count(), 3);
+
+        assert!(log.clear(b + 1).is_err());
+        assert!(log.clear(log.end()).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn copy_filtered() -> Result<(), Error> {
+        let mut src = temp_offset_log();
+        src.append_batch(&[&b"alice: hi"[..], b"bob: yo", b"alice: bye", b"carol: hey"])?;
+
+        let mut dest = OffsetLog::<u64>::from_file(tempfile()?)?;
+        let copied = src.copy_filtered(&mut dest, |b| b.starts_with(b"alice:"))?;
+        assert_eq!(copied, 2);
+        assert_eq!(src.iter().count(), 4);
+
+        let entries: Vec<Vec<u8>> = dest.iter().map(|e| e.data).collect();
+        assert_eq!(entries, &[&b"alice: hi"[..], b"alice: bye"]);
+        assert_eq!(dest.get(dest.latest().unwrap())?, b"alice: bye");
+        Ok(())
+    }
+
+    #[test]
+    fn copy_filtered_transformed() -> Result<(), Error> {
+        let reversed = |log: &mut OffsetLog<u32>| {
+            log.set_body_transform(Box::new(|b| Ok(b.iter().rev().cloned().collect())));
+            log.set_body_inverse(Box::new(|b| Ok(b.iter().rev().cloned().collect())));
+        };
+        let mut src = temp_offset_log();
+        reversed(&mut src);
+        let offsets = src.append_batch(&[&b"alice: hi"[..], b"bob: yo", b"alice: bye"])?;
+
+        let mut dest = temp_offset_log();
+        reversed(&mut dest);
+        let copied = src.copy_filtered(&mut dest, |b| b.starts_with(b"alice:"))?;
+        assert_eq!(copied, 2);
+
+        let stored: Vec<Vec<u8>> = dest.iter().map(|e| e.data).collect();
+        assert_eq!(stored, &[&b"ih :ecila"[..], b"eyb :ecila"]);
+        assert_eq!(dest.get(dest.latest().unwrap())?, b"alice: bye");
+
+        // A corrupt frame is an error, not the end of the log.
+        src.file.write_at(&[0, 0, 0, 99], offsets[2] - 8)?;
+        let mut dest = temp_offset_log();
+        let err = src.copy_filtered(&mut dest, |_| true).unwrap_err();
+        match err.downcast::<FlumeOffsetLogError>()? {
+            FlumeOffsetLogError::CorruptAt { offset } => assert_eq!(offset, offsets[1]),
+            e => panic!("unexpected error: {}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn rev_iter() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        assert_eq!(log.rev_iter().count(), 0);
+
+        let offsets = (0..10u8)
+            .map(|i| log.append(&[i; 3]))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let entries: Vec<(u64, Vec<u8>)> = log.rev_iter().map(|e| (e.offset, e.data)).collect();
+        let expected: Vec<(u64, Vec<u8>)> = (0..10u8)
+            .rev()
+            .map(|i| (offsets[i as usize], vec![i; 3]))
+            .collect();
+        assert_eq!(entries, expected);
+
+        // Breaking the trailing size of an entry ends the iteration there.
+        log.file.write_at(&[0xff; 4], offsets[6] - 8)?;
+        let data: Vec<Vec<u8>> = log.rev_iter().map(|e| e.data).collect();
+        assert_eq!(data, [[9; 3], [8; 3], [7; 3], [6; 3]]);
+
+        // A log with a torn last frame can't be read backward from its end.
+        log.file.set_len(log.end() - 1)?;
+        let err = OffsetLog::<u32>::from_file(log.file.try_clone()?)
+            .err()
+            .unwrap();
+        match err.downcast::<FlumeOffsetLogError>()? {
+            FlumeOffsetLogError::CorruptLogFile {} => {}
+            e => panic!("unexpected error: {}", e),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn iter_recovering() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let offsets = log.append_batch(&[b"aaaa", b"bbbb", b"cccc", b"dddd"])?;
+        let end = log.end();
+        assert!(log.iter_recovering()?.all(|r| r.is_ok()));
+
+        // Break the trailing size of the second entry, and tear a frame
+        // onto the end of the log.
+        log.file.write_at(&[0, 0, 0, 9], offsets[2] - 8)?;
+        log.file.write_at(&[0, 0, 0, 100, b'e'], end)?;
+
+        let results: Vec<Result<(u64, Vec<u8>), CorruptRegion>> = log
+            .iter_recovering()?
+            .map(|r| r.map(|e| (e.offset, e.data)))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Ok((offsets[0], b"aaaa".to_vec())),
+                Err(CorruptRegion {
+                    start: offsets[1],
+                    end: offsets[2]
+                }),
+                Ok((offsets[2], b"cccc".to_vec())),
+                Ok((offsets[3], b"dddd".to_vec())),
+                Err(CorruptRegion {
+                    start: end,
+                    end: end + 5
+                }),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn iter_recovering_large_corrupt_region() -> Result<(), Error> {
+        let mut log = temp_offset_log();
+        let a = log.append(b"abc")?;
+
+        // A long run of noise, some of which looks like frame sizes.
+        let mut state = 0x2545_f491_u32;
+        let noise: Vec<u8> = (0..1 << 20)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                (state % 7) as u8
+            })
+            .collect();
+        let noise_start = log.end();
+        log.file.write_at(&noise, noise_start)?;
+        log.end_of_file += noise.len() as u64;
+        let b = log.append(b"defgh")?;
+
+        let results: Vec<Result<(u64, Vec<u8>), CorruptRegion>> = log
+            .iter_recovering()?
+            .map(|r| r.map(|e| (e.offset, e.data)))
+            .collect();
+        assert_eq!(
+            results,
+            vec![
+                Ok((a, b"abc".to_vec())),
+                Err(CorruptRegion {
+                    start: noise_start,
+                    end: b
+                }),
+                Ok((b, b"defgh".to_vec())),
+            ]
+        );
+        Ok(())
+    }
+
     #[test]
     fn bidir_iter() -> Result<(), Error> {
         let mut log = temp_offset_log();
@@ -0,0 +1,194 @@
+use crate::flume_log::*;
+use crate::log_entry::LogEntry;
+use crate::offset_log::{OffsetLog, OffsetType};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const SEGMENT_SUFFIX: &str = ".log";
+
+struct Segment<ByteType> {
+    base: u64,
+    log: OffsetLog<ByteType>,
+}
+
+/// An `OffsetLog` split across a directory of segment files.
+///
+/// Each segment is named after its base offset, the global offset of its
+/// first entry, so entries keep the same offset however many segments there
+/// are. A new segment is started once the current one is at least
+/// `segment_size` bytes long.
+pub struct SegmentedOffsetLog<ByteType> {
+    dir: PathBuf,
+    segment_size: u64,
+    segments: Vec<Segment<ByteType>>,
+}
+
+impl<ByteType: OffsetType> SegmentedOffsetLog<ByteType> {
+    pub fn open<P: AsRef<Path>>(
+        dir: P,
+        segment_size: u64,
+    ) -> Result<SegmentedOffsetLog<ByteType>, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+
+        let mut bases = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let base = name
+                .to_str()
+                .and_then(|n| n.strip_suffix(SEGMENT_SUFFIX))
+                .and_then(|n| n.parse::<u64>().ok());
+            if let Some(base) = base {
+                bases.push(base);
+            }
+        }
+        bases.sort_unstable();
+
+        let mut log = SegmentedOffsetLog {
+            dir,
+            segment_size,
+            segments: Vec::new(),
+        };
+        for base in bases {
+            log.open_segment(base)?;
+        }
+        if log.segments.is_empty() {
+            log.open_segment(0)?;
+        }
+        Ok(log)
+    }
+
+    fn open_segment(&mut self, base: u64) -> Result<(), Error> {
+        let path = self.dir.join(format!("{:020}{}", base, SEGMENT_SUFFIX));
+        let log = OffsetLog::new(path)?;
+        self.segments.push(Segment { base, log });
+        Ok(())
+    }
+
+    fn current(&mut self) -> &mut Segment<ByteType> {
+        self.segments.last_mut().unwrap()
+    }
+
+    // The index of the segment holding `offset`, ie. the last one starting
+    // at or before it.
+    fn segment_index(&self, offset: u64) -> Option<usize> {
+        self.segments
+            .partition_point(|s| s.base <= offset)
+            .checked_sub(1)
+    }
+
+    /// The global offset one past the last entry.
+    pub fn end(&self) -> u64 {
+        let last = self.segments.last().unwrap();
+        last.base + last.log.end()
+    }
+
+    pub fn segment_count(&self) -> usize {
+        self.segments.len()
+    }
+
+    /// Iterate over the entries of every segment, in order, with global
+    /// offsets.
+    pub fn iter(&self) -> impl Iterator<Item = LogEntry> + '_ {
+        self.segments.iter().flat_map(|s| {
+            let base = s.base;
+            s.log.iter().map(move |mut e| {
+                e.offset += base;
+                e
+            })
+        })
+    }
+}
+
+impl<ByteType: OffsetType> FlumeLog for SegmentedOffsetLog<ByteType> {
+    fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
+        match self.segment_index(seq_num).map(|i| &self.segments[i]) {
+            Some(s) => s.log.get(seq_num - s.base),
+            None => Err(FlumeLogError::SequenceNotFound { sequence: seq_num }.into()),
+        }
+    }
+
+    fn latest(&self) -> Option<u64> {
+        self.segments
+            .iter()
+            .rev()
+            .find_map(|s| s.log.latest().map(|o| s.base + o))
+    }
+
+    fn append(&mut self, buff: &[u8]) -> Result<u64, Error> {
+        if self.current().log.end() >= self.segment_size {
+            let end = self.end();
+            self.open_segment(end)?;
+        }
+
+        let segment = self.current();
+        let offset = segment.log.append(buff)?;
+        Ok(segment.base + offset)
+    }
+
+    fn clear(&mut self, seq_num: u64) -> Result<(), Error> {
+        match self.segment_index(seq_num).map(|i| &mut self.segments[i]) {
+            Some(s) => s.log.clear(seq_num - s.base),
+            None => Err(FlumeLogError::SequenceNotFound { sequence: seq_num }.into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::FlumeLog;
+    use crate::segmented_offset_log::*;
+
+    extern crate tempfile;
+    use self::tempfile::tempdir;
+
+    #[test]
+    fn append_across_segments() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let mut log = SegmentedOffsetLog::<u32>::open(dir.path(), 32)?;
+
+        // Each entry is 12 bytes of framing plus 10 bytes of data.
+        let a = log.append(b"0123456789")?;
+        let b = log.append(b"abcdefghij")?;
+        assert_eq!(log.segment_count(), 1);
+
+        let c = log.append(b"ABCDEFGHIJ")?;
+        assert_eq!(log.segment_count(), 2);
+        assert_eq!((a, b, c), (0, 22, 44));
+        assert_eq!(log.latest(), Some(c));
+        assert_eq!(log.end(), 66);
+
+        assert_eq!(log.get(a)?, b"0123456789");
+        assert_eq!(log.get(c)?, b"ABCDEFGHIJ");
+        assert!(log.get(log.end()).is_err());
+
+        log.clear(b)?;
+        assert_eq!(log.get(b)?, vec![0; 10]);
+        assert_eq!(log.get(c)?, b"ABCDEFGHIJ");
+        Ok(())
+    }
+
+    #[test]
+    fn iter_and_reopen() -> Result<(), Error> {
+        let dir = tempdir()?;
+        let mut offsets = Vec::new();
+        {
+            let mut log = SegmentedOffsetLog::<u64>::open(dir.path(), 40)?;
+            for i in 0..10u8 {
+                offsets.push(log.append(&[i; 8])?);
+            }
+            assert!(log.segment_count() > 1);
+        }
+
+        let log = SegmentedOffsetLog::<u64>::open(dir.path(), 40)?;
+        let entries: Vec<(u64, Vec<u8>)> = log.iter().map(|e| (e.offset, e.data)).collect();
+        let expected: Vec<(u64, Vec<u8>)> = (0..10u8)
+            .map(|i| (offsets[i as usize], vec![i; 8]))
+            .collect();
+        assert_eq!(entries, expected);
+
+        assert_eq!(log.get(offsets[1])?, vec![1; 8]);
+        assert_eq!(log.latest(), Some(offsets[9]));
+        Ok(())
+    }
+}
@@ -0,0 +1,206 @@
+use crate::flume_log::*;
+use crate::offset_log::{OffsetLog, OffsetType};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::File;
+use std::marker::PhantomData;
+use std::path::Path;
+
+/// How a `TypedOffsetLog` turns values into entry data and back.
+pub trait Format {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, Error>;
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error>;
+}
+
+/// JSON, using `serde_json`. Readable, but the largest and slowest.
+pub struct JsonFormat;
+
+impl Format for JsonFormat {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+        Ok(serde_json::to_vec(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+        Ok(serde_json::from_slice(bytes)?)
+    }
+}
+
+/// CBOR, using `serde_cbor`. Self-describing like JSON, but binary.
+pub struct CborFormat;
+
+impl Format for CborFormat {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+        Ok(serde_cbor::to_vec(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+}
+
+/// bincode, the most compact of the three. It isn't self-describing, so
+/// entries can only be read back as the exact type they were written as.
+#[cfg(feature = "bincode")]
+pub struct BincodeFormat;
+
+#[cfg(feature = "bincode")]
+impl Format for BincodeFormat {
+    fn serialize<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+        Ok(bincode::serialize(value)?)
+    }
+
+    fn deserialize<T: DeserializeOwned>(bytes: &[u8]) -> Result<T, Error> {
+        Ok(bincode::deserialize(bytes)?)
+    }
+}
+
+/// An `OffsetLog` of values of type `T`, serialized with `F` (JSON by
+/// default).
+///
+/// The underlying log is an ordinary `OffsetLog<ByteType>` holding the
+/// serialized bytes, and can be reached with `log` for anything the typed
+/// interface doesn't cover. Nothing in the log records the format, so a
+/// log must always be opened with the format it was written with; mixing
+/// formats in one log is unsupported.
+pub struct TypedOffsetLog<T, ByteType, F = JsonFormat> {
+    log: OffsetLog<ByteType>,
+    value_type: PhantomData<T>,
+    format: PhantomData<F>,
+}
+
+impl<T, ByteType, F> TypedOffsetLog<T, ByteType, F>
+where
+    T: Serialize + DeserializeOwned,
+    F: Format,
+{
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<TypedOffsetLog<T, ByteType, F>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        Ok(TypedOffsetLog::from_offset_log(OffsetLog::new(path)?))
+    }
+
+    pub fn from_file(file: File) -> Result<TypedOffsetLog<T, ByteType, F>, Error>
+    where
+        ByteType: OffsetType,
+    {
+        Ok(TypedOffsetLog::from_offset_log(OffsetLog::from_file(file)?))
+    }
+
+    pub fn from_offset_log(log: OffsetLog<ByteType>) -> TypedOffsetLog<T, ByteType, F> {
+        TypedOffsetLog {
+            log,
+            value_type: PhantomData,
+            format: PhantomData,
+        }
+    }
+
+    pub fn log(&self) -> &OffsetLog<ByteType> {
+        &self.log
+    }
+
+    pub fn append(&mut self, value: &T) -> Result<Sequence, Error> {
+        self.log.append(&F::serialize(value)?)
+    }
+
+    pub fn get(&self, seq: Sequence) -> Result<T, Error> {
+        F::deserialize(&self.log.get(seq)?)
+    }
+
+    pub fn latest(&self) -> Option<Sequence> {
+        self.log.latest()
+    }
+
+    /// Iterate over the values in the log, with their sequence numbers.
+    /// Entries that don't deserialize as `T` are yielded as errors.
+    pub fn iter(&self) -> impl Iterator<Item = (Sequence, Result<T, Error>)> {
+        self.log.iter().map(|e| {
+            let value = F::deserialize(&e.data);
+            (e.offset, value)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::typed_offset_log::*;
+
+    extern crate tempfile;
+    use self::tempfile::tempfile;
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Post {
+        author: String,
+        text: String,
+        likes: u32,
+    }
+
+    fn post(author: &str, text: &str, likes: u32) -> Post {
+        Post {
+            author: author.to_string(),
+            text: text.to_string(),
+            likes,
+        }
+    }
+
+    #[test]
+    fn round_trip_struct() -> Result<(), Error> {
+        let mut log = TypedOffsetLog::<Post, u32>::from_file(tempfile()?)?;
+        let a = log.append(&post("@alice", "hello", 3))?;
+        let b = log.append(&post("@bob", "hi alice", 0))?;
+
+        assert_eq!(log.get(a)?, post("@alice", "hello", 3));
+        assert_eq!(log.get(b)?, post("@bob", "hi alice", 0));
+        assert_eq!(log.latest(), Some(b));
+
+        // The underlying log holds plain JSON.
+        let raw: serde_json::Value = serde_json::from_slice(&log.log().get(b)?)?;
+        assert_eq!(raw["author"], "@bob");
+
+        let seqs: Vec<Sequence> = log
+            .iter()
+            .map(|(seq, value)| {
+                assert!(value.is_ok());
+                seq
+            })
+            .collect();
+        assert_eq!(seqs, [a, b]);
+        Ok(())
+    }
+
+    fn round_trip_format<F: Format>() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut log = TypedOffsetLog::<Post, u64, F>::from_file(file.try_clone()?)?;
+        let a = log.append(&post("@alice", "hello", 3))?;
+        let b = log.append(&post("@bob", "", u32::MAX))?;
+
+        let log = TypedOffsetLog::<Post, u64, F>::from_file(file)?;
+        assert_eq!(log.get(a)?, post("@alice", "hello", 3));
+        assert_eq!(log.get(b)?, post("@bob", "", u32::MAX));
+        assert_eq!(log.iter().count(), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_cbor() -> Result<(), Error> {
+        round_trip_format::<CborFormat>()
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn round_trip_bincode() -> Result<(), Error> {
+        round_trip_format::<BincodeFormat>()
+    }
+
+    #[test]
+    fn get_wrong_type() -> Result<(), Error> {
+        let file = tempfile()?;
+        let mut raw = OffsetLog::<u32>::from_file(file.try_clone()?)?;
+        let seq = raw.append(b"{\"something\": \"else\"}")?;
+
+        let log = TypedOffsetLog::<Post, u32>::from_file(file)?;
+        assert!(log.get(seq).is_err());
+        assert!(log.iter().next().unwrap().1.is_err());
+        Ok(())
+    }
+}
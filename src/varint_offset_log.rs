@@ -0,0 +1,493 @@
+use crate::flume_log::*;
+use crate::log_entry::LogEntry;
+use crate::offset_log::{write_all_at, BidirIterator, Forward, ReadResult};
+use buffered_offset_reader::{BufOffsetReader, OffsetRead, OffsetReadMut};
+use bytes::{BufMut, BytesMut};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::path::Path;
+
+#[derive(Debug, Fail)]
+pub enum VarintOffsetLogError {
+    #[fail(display = "Incorrect framing values detected, log file might be corrupt")]
+    CorruptLogFile {},
+
+    #[fail(display = "The decode buffer passed to decode was too small")]
+    DecodeBufferSizeTooSmall {},
+
+    #[fail(display = "The file does not start with a varint log header")]
+    BadHeader {},
+
+    #[fail(display = "Offset {} is not the start of an entry in the log", offset)]
+    InvalidOffset { offset: u64 },
+}
+
+// Every varint log starts with this header; the last byte is the format version.
+const HEADER: &[u8] = b"FLUMEVI\x01";
+const MAX_VARINT_SIZE: usize = 10;
+// Entries are read in pieces of at most this size. See `read_entry`.
+const READ_CHUNK_SIZE: usize = 64 * 1024;
+
+/// A log like `OffsetLog`, but with LEB128 varint lengths in place of the
+/// fixed-width framing, so an entry under 128 bytes has 2 bytes of framing
+/// rather than 12 or more.
+///
+/// An entry is `[size: varint, data, size: reversed varint]`. The trailing
+/// size is written with its bytes reversed, so it can be decoded while
+/// reading backward from the end of the entry. Offsets are file positions,
+/// and the first entry is at the end of the header.
+pub struct VarintOffsetLog {
+    pub file: File,
+    end_of_file: u64,
+    last_offset: Option<u64>,
+    tmp_buffer: BytesMut,
+}
+
+impl VarintOffsetLog {
+    pub fn new<P: AsRef<Path>>(path: P) -> Result<VarintOffsetLog, Error> {
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        VarintOffsetLog::from_file(file)
+    }
+
+    pub fn from_file(file: File) -> Result<VarintOffsetLog, Error> {
+        let mut file_length = file.metadata()?.len();
+        if file_length == 0 {
+            write_all_at(&file, HEADER, 0)?;
+            file_length = HEADER.len() as u64;
+        } else {
+            let mut header = [0; HEADER.len()];
+            if file.read_at(&mut header, 0)? < HEADER.len() || header != HEADER {
+                return Err(VarintOffsetLogError::BadHeader {}.into());
+            }
+        }
+
+        let last_offset = if file_length > HEADER.len() as u64 {
+            Some(read_prev(file_length, &file)?.entry.offset)
+        } else {
+            None
+        };
+
+        Ok(VarintOffsetLog {
+            file,
+            end_of_file: file_length,
+            last_offset,
+            tmp_buffer: BytesMut::new(),
+        })
+    }
+
+    pub fn end(&self) -> u64 {
+        self.end_of_file
+    }
+
+    /// Read the entry at `offset`. An offset that isn't the start of a
+    /// whole entry in the log is an `InvalidOffset` error, and the entry's
+    /// size is checked against the end of the log before it's read.
+    pub fn read(&self, offset: u64) -> Result<ReadResult, Error> {
+        let invalid = || -> Error { VarintOffsetLogError::InvalidOffset { offset }.into() };
+        if offset < HEADER.len() as u64 || offset >= self.end_of_file {
+            return Err(invalid());
+        }
+
+        // IO errors are passed on; anything wrong with the entry itself
+        // means `offset` didn't point at one.
+        let check = |e: Error| match e.downcast_ref::<VarintOffsetLogError>() {
+            Some(_) => invalid(),
+            None => e,
+        };
+
+        let mut read_at = |b: &mut [u8], o| self.file.read_at(b, o);
+        let (data_size, head_size) = read_head(offset, &mut read_at).map_err(check)?;
+        data_size
+            .checked_add(2 * head_size as u64)
+            .filter(|&size| size <= self.end_of_file - offset)
+            .ok_or_else(invalid)?;
+        read_entry(offset, head_size, data_size as usize, &mut read_at).map_err(check)
+    }
+
+    pub fn iter(&self) -> Forward<VarintOffsetLogIter> {
+        self.bidir_iter().forward_owned()
+    }
+
+    pub fn bidir_iter(&self) -> VarintOffsetLogIter {
+        VarintOffsetLogIter::with_starting_offset(
+            self.file.try_clone().unwrap(),
+            HEADER.len() as u64,
+        )
+    }
+}
+
+impl FlumeLog for VarintOffsetLog {
+    fn get(&self, seq_num: u64) -> Result<Vec<u8>, Error> {
+        self.read(seq_num).map(|r| r.entry.data)
+    }
+
+    fn latest(&self) -> Option<u64> {
+        self.last_offset
+    }
+
+    fn append(&mut self, buff: &[u8]) -> Result<u64, Error> {
+        self.tmp_buffer.clear();
+        encode(buff, &mut self.tmp_buffer);
+
+        let offset = self.end_of_file;
+        write_all_at(&self.file, &self.tmp_buffer, offset)?;
+
+        self.end_of_file += self.tmp_buffer.len() as u64;
+        self.last_offset = Some(offset);
+        Ok(offset)
+    }
+
+    fn clear(&mut self, seq_num: u64) -> Result<(), Error> {
+        let data_size = self.read(seq_num)?.entry.data.len();
+        let data_start = seq_num + varint_size(data_size as u64) as u64;
+        write_all_at(&self.file, &vec![0; data_size], data_start)?;
+        Ok(())
+    }
+}
+
+pub struct VarintOffsetLogIter {
+    reader: BufOffsetReader<File>,
+    current: u64,
+    next: u64,
+}
+
+impl VarintOffsetLogIter {
+    pub fn with_starting_offset(file: File, offset: u64) -> VarintOffsetLogIter {
+        VarintOffsetLogIter {
+            reader: BufOffsetReader::new(file),
+            current: offset,
+            next: offset,
+        }
+    }
+}
+
+impl BidirIterator for VarintOffsetLogIter {
+    type Item = LogEntry;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.current = self.next;
+        let r = read_next_impl(self.current, |b, o| self.reader.read_at(b, o)).ok()?;
+        self.next = r.next;
+        Some(r.entry)
+    }
+
+    fn prev(&mut self) -> Option<Self::Item> {
+        self.next = self.current;
+        let r = read_prev_impl(self.current, |b, o| self.reader.read_at(b, o)).ok()?;
+        self.current = r.entry.offset;
+        Some(r.entry)
+    }
+}
+
+pub fn varint_size(mut value: u64) -> usize {
+    let mut size = 1;
+    while value >= 0x80 {
+        value >>= 7;
+        size += 1;
+    }
+    size
+}
+
+pub fn encode_varint(mut value: u64, dest: &mut BytesMut) {
+    while value >= 0x80 {
+        dest.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    dest.put_u8(value as u8);
+}
+
+/// Decode a varint from the start of `bytes`, returning the value and the
+/// number of bytes it took up.
+pub fn decode_varint(bytes: &[u8]) -> Option<(u64, usize)> {
+    decode_varint_from(bytes.iter())
+}
+
+// Decode a reversed varint from the end of `bytes`.
+fn decode_varint_rev(bytes: &[u8]) -> Option<(u64, usize)> {
+    decode_varint_from(bytes.iter().rev())
+}
+
+fn decode_varint_from<'a, I: Iterator<Item = &'a u8>>(bytes: I) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (i, b) in bytes.take(MAX_VARINT_SIZE).enumerate() {
+        value |= u64::from(b & 0x7f) << (7 * i);
+        if b & 0x80 == 0 {
+            return Some((value, i + 1));
+        }
+    }
+    None
+}
+
+pub fn encode(item: &[u8], dest: &mut BytesMut) {
+    let size = item.len() as u64;
+    let size_bytes = varint_size(size);
+    dest.reserve(size_bytes * 2 + item.len());
+
+    encode_varint(size, dest);
+    dest.put_slice(item);
+
+    let tail_start = dest.len();
+    encode_varint(size, dest);
+    dest[tail_start..].reverse();
+}
+
+pub fn read_next<R: OffsetRead>(offset: u64, r: &R) -> Result<ReadResult, Error> {
+    read_next_impl(offset, |b, o| r.read_at(b, o))
+}
+
+pub fn read_prev<R: OffsetRead>(offset: u64, r: &R) -> Result<ReadResult, Error> {
+    read_prev_impl(offset, |b, o| r.read_at(b, o))
+}
+
+fn read_next_impl<F>(offset: u64, mut read_at: F) -> Result<ReadResult, Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    let (data_size, head_size) = read_head(offset, &mut read_at)?;
+    read_entry(offset, head_size, data_size as usize, &mut read_at)
+}
+
+// Read the size at the start of the entry at `offset`, returning it and the
+// number of bytes it took up.
+fn read_head<F>(offset: u64, read_at: &mut F) -> Result<(u64, usize), Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    let mut head = [0; MAX_VARINT_SIZE];
+    let n = read_at(&mut head, offset)?;
+    Ok(decode_varint(&head[..n]).ok_or(VarintOffsetLogError::DecodeBufferSizeTooSmall {})?)
+}
+
+fn read_prev_impl<F>(offset: u64, mut read_at: F) -> Result<ReadResult, Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    let first_entry = HEADER.len() as u64;
+    if offset <= first_entry {
+        return Err(VarintOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    }
+
+    let mut tail = [0; MAX_VARINT_SIZE];
+    let tail_len = (offset - first_entry).min(MAX_VARINT_SIZE as u64) as usize;
+    let tail = &mut tail[..tail_len];
+    if read_at(tail, offset - tail_len as u64)? < tail_len {
+        return Err(VarintOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+    }
+    let (data_size, size_bytes) =
+        decode_varint_rev(tail).ok_or(VarintOffsetLogError::CorruptLogFile {})?;
+
+    let entry_size = data_size
+        .checked_add(2 * size_bytes as u64)
+        .filter(|&size| size <= offset - first_entry)
+        .ok_or(VarintOffsetLogError::CorruptLogFile {})?;
+
+    read_entry(
+        offset - entry_size,
+        size_bytes,
+        data_size as usize,
+        &mut read_at,
+    )
+}
+
+fn read_entry<F>(
+    offset: u64,
+    head_size: usize,
+    data_size: usize,
+    read_at: &mut F,
+) -> Result<ReadResult, Error>
+where
+    F: FnMut(&mut [u8], u64) -> io::Result<usize>,
+{
+    let to_read = data_size
+        .checked_add(head_size)
+        .ok_or(VarintOffsetLogError::CorruptLogFile {})?;
+
+    // The size hasn't been checked against anything yet, so the buffer only
+    // grows as data is actually read, and a corrupt size can't allocate more
+    // than the rest of the file holds.
+    let mut buf = Vec::new();
+    while buf.len() < to_read {
+        let start = buf.len();
+        buf.resize(start + (to_read - start).min(READ_CHUNK_SIZE), 0);
+        let n = read_at(&mut buf[start..], offset + (head_size + start) as u64)?;
+        if n == 0 {
+            return Err(VarintOffsetLogError::DecodeBufferSizeTooSmall {}.into());
+        }
+        buf.truncate(start + n);
+    }
+
+    match decode_varint_rev(&buf[data_size..]) {
+        Some((size, n)) if size == data_size as u64 && n == head_size => {}
+        _ => return Err(VarintOffsetLogError::CorruptLogFile {}.into()),
+    }
+
+    buf.truncate(data_size);
+    Ok(ReadResult {
+        entry: LogEntry { offset, data: buf },
+        next: offset + (to_read + head_size) as u64,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use crate::flume_log::FlumeLog;
+    use crate::varint_offset_log::*;
+
+    extern crate tempfile;
+    use self::tempfile::tempfile;
+
+    fn temp_varint_log() -> VarintOffsetLog {
+        VarintOffsetLog::from_file(tempfile().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn varint_round_trip() {
+        let values = [
+            0,
+            1,
+            127,
+            128,
+            300,
+            16_383,
+            16_384,
+            u32::MAX as u64,
+            u64::MAX,
+        ];
+        let sizes = [1, 1, 1, 2, 2, 2, 3, 5, 10];
+
+        for (&value, &size) in values.iter().zip(sizes.iter()) {
+            let mut buf = BytesMut::new();
+            encode_varint(value, &mut buf);
+            assert_eq!(buf.len(), size);
+            assert_eq!(varint_size(value), size);
+            assert_eq!(decode_varint(&buf), Some((value, size)));
+
+            buf.reverse();
+            assert_eq!(decode_varint_rev(&buf), Some((value, size)));
+        }
+
+        assert_eq!(decode_varint(&[0x80, 0x80]), None);
+    }
+
+    #[test]
+    fn encode_entry() {
+        let mut buf = BytesMut::new();
+        encode(&[1, 2, 3], &mut buf);
+        assert_eq!(&buf[..], &[3, 1, 2, 3, 3]);
+
+        let item = vec![7; 300];
+        let mut buf = BytesMut::new();
+        encode(&item, &mut buf);
+        assert_eq!(buf.len(), 304);
+        assert_eq!(&buf[..2], &[0xac, 0x02]);
+        assert_eq!(&buf[302..], &[0x02, 0xac]);
+    }
+
+    #[test]
+    fn append_get_and_iter() -> Result<(), Error> {
+        let mut log = temp_varint_log();
+        assert_eq!(log.latest(), None);
+
+        let sizes = [0, 1, 127, 128, 20_000];
+        let mut offsets = Vec::new();
+        for &size in sizes.iter() {
+            offsets.push(log.append(&vec![size as u8; size])?);
+        }
+        assert_eq!(offsets[0], HEADER.len() as u64);
+        assert_eq!(offsets[2] - offsets[1], 3);
+        assert_eq!(log.latest(), Some(offsets[4]));
+
+        for (&offset, &size) in offsets.iter().zip(sizes.iter()) {
+            assert_eq!(log.get(offset)?, vec![size as u8; size]);
+        }
+
+        let entries: Vec<(u64, usize)> = log.iter().map(|e| (e.offset, e.data.len())).collect();
+        let expected: Vec<(u64, usize)> =
+            offsets.iter().cloned().zip(sizes.iter().cloned()).collect();
+        assert_eq!(entries, expected);
+
+        let mut iter = log.bidir_iter();
+        while iter.next().is_some() {}
+        let back: Vec<usize> = std::iter::from_fn(|| iter.prev())
+            .map(|e| e.data.len())
+            .collect();
+        assert_eq!(back, &[20_000, 128, 127, 1, 0]);
+        Ok(())
+    }
+
+    #[test]
+    fn reopen() -> Result<(), Error> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("varint");
+
+        let latest = {
+            let mut log = VarintOffsetLog::new(&path)?;
+            log.append(b"abc")?;
+            log.append(&[9; 200])?
+        };
+
+        let mut log = VarintOffsetLog::new(&path)?;
+        assert_eq!(log.latest(), Some(latest));
+        assert_eq!(log.get(latest)?, vec![9; 200]);
+
+        let next = log.append(b"def")?;
+        log.clear(latest)?;
+        assert_eq!(log.get(latest)?, vec![0; 200]);
+        assert_eq!(log.iter().map(|e| e.offset).last(), Some(next));
+        Ok(())
+    }
+
+    #[test]
+    fn iter_stops_at_huge_size() -> Result<(), Error> {
+        let mut log = temp_varint_log();
+        let a = log.append(b"abc")?;
+        // A 10 byte varint head that decodes to a size of 2^63 - 1.
+        let mut head = [0xff; MAX_VARINT_SIZE];
+        head[MAX_VARINT_SIZE - 1] = 0;
+        write_all_at(&log.file, &head, log.end())?;
+        write_all_at(&log.file, b"defgh", log.end() + head.len() as u64)?;
+
+        let entries: Vec<(u64, Vec<u8>)> = log.iter().map(|e| (e.offset, e.data)).collect();
+        assert_eq!(entries, [(a, b"abc".to_vec())]);
+        assert!(read_next(log.end(), &log.file).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn read_rejects_bad_offsets() -> Result<(), Error> {
+        let mut log = temp_varint_log();
+        let a = log.append(b"abc")?;
+        // An entry whose data happens to look like a huge varint size.
+        let b = log.append(&[0xff; 9])?;
+        let c = log.append(b"defgh")?;
+
+        for offset in [0, a + 1, b + 1, b + 2, c + 3, log.end(), log.end() + 100] {
+            match log
+                .get(offset)
+                .unwrap_err()
+                .downcast::<VarintOffsetLogError>()?
+            {
+                VarintOffsetLogError::InvalidOffset { offset: o } => assert_eq!(o, offset),
+                e => panic!("unexpected error: {}", e),
+            }
+        }
+        assert_eq!(log.get(a)?, b"abc");
+        assert_eq!(log.get(c)?, b"defgh");
+        Ok(())
+    }
+
+    #[test]
+    fn rejects_other_formats() -> Result<(), Error> {
+        let mut log = crate::offset_log::OffsetLog::<u32>::from_file(tempfile()?)?;
+        log.append(b"not a varint log")?;
+
+        assert!(VarintOffsetLog::from_file(log.file.try_clone()?).is_err());
+        Ok(())
+    }
+}